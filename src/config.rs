@@ -1 +1,346 @@
-pub const CANVAS_SIZES: [(u32, u32); 3] = [(500, 281), (500, 540), (960, 540)];
\ No newline at end of file
+use crate::modules::agemap::Falloff;
+use crate::modules::composite::CompositeView;
+use crate::util::color::hex_to_rgba;
+use crate::util::encoder::VideoFormat;
+use crate::util::expansion::{ExpansionEntry, ExpansionSchedule};
+use crate::util::highlight::HighlightStyle;
+use crate::util::keyframes::Keyframes;
+use crate::util::leaderboard::LeaderboardMode;
+use crate::util::render::{Pacing, PacingMode};
+use crate::util::replay::ModActions;
+use crate::util::time::{parse_timestamp, TimeWindow};
+use image::Rgba;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+const DEFAULT_CONFIG: &str = "canvas.toml";
+const EVENT_START: &str = "2025-02-28 17:00:00";
+const PALETTE: [&str; 33] = [
+    "#000000", "#525252", "#888D90", "#D5D6D8", "#FFFFFF", "#6B0119", "#BD0037", "#FF4500",
+    "#FEA800", "#FFD435", "#FEF889", "#01A267", "#09CC76", "#7EEC57", "#02756D", "#009DAA",
+    "#00CCBE", "#244FA4", "#3790EA", "#52E8F3", "#4839BF", "#695BFF", "#94B3FF", "#801D9F",
+    "#B449BF", "#E4ABFD", "#DD117E", "#FE3781", "#FE99A9", "#6D462F", "#9B6926", "#FEB470",
+    "#F4CEBE",
+];
+const EXPANSIONS: [(&str, u32, u32); 3] = [
+    ("2025-02-28 17:00:00", 500, 281),
+    ("2025-03-01 17:00:00", 500, 540),
+    ("2025-03-02 17:00:00", 960, 540),
+];
+
+/// The event description, read from `canvas.toml`. Everything is optional and
+/// defaults to the osucord 2025 event.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub name: String,
+    pub database: Option<String>,
+    pub start: String,
+    pub end: Option<String>,
+    pub output: PathBuf,
+    pub palette: Vec<String>,
+    pub expansions: Vec<ExpansionEntry>,
+    pub mod_actions: ModActions,
+    pub video: VideoConfig,
+    pub timelapse: TimelapseConfig,
+    pub virginmap: VirginmapConfig,
+    pub agemap: AgemapConfig,
+    pub composite: CompositeConfig,
+    pub heatmap: HeatmapConfig,
+    pub longsession: LongsessionConfig,
+    pub maincontributors: MaincontributorsConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            name: "canvas".to_string(),
+            database: None,
+            start: EVENT_START.to_string(),
+            end: None,
+            output: PathBuf::from("output"),
+            palette: PALETTE.iter().map(|hex| hex.to_string()).collect(),
+            expansions: EXPANSIONS
+                .iter()
+                .map(|(at, width, height)| ExpansionEntry {
+                    at: at.to_string(),
+                    x: 0,
+                    y: 0,
+                    width: *width,
+                    height: *height,
+                })
+                .collect(),
+            mod_actions: ModActions::Wipe,
+            video: VideoConfig::default(),
+            timelapse: TimelapseConfig::default(),
+            virginmap: VirginmapConfig::default(),
+            agemap: AgemapConfig::default(),
+            composite: CompositeConfig::default(),
+            heatmap: HeatmapConfig::default(),
+            longsession: LongsessionConfig::default(),
+            maincontributors: MaincontributorsConfig::default(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VideoConfig {
+    pub format: VideoFormat,
+    pub quality: Option<u8>,
+    pub preset: String,
+    pub padding: u32,
+    pub scale: Option<u32>,
+    pub resolution: Option<String>,
+    pub hud: bool,
+    pub active_minutes: i64,
+    pub region: Option<String>,
+    pub camera: Option<PathBuf>,
+    pub follow: bool,
+    pub follow_minutes: i64,
+    pub max_zoom: f64,
+}
+
+impl Default for VideoConfig {
+    fn default() -> Self {
+        VideoConfig {
+            format: VideoFormat::Mp4,
+            quality: None,
+            preset: "veryslow".to_string(),
+            padding: 0,
+            scale: None,
+            resolution: None,
+            hud: false,
+            active_minutes: 10,
+            region: None,
+            camera: None,
+            follow: false,
+            follow_minutes: 30,
+            max_zoom: 8.0,
+        }
+    }
+}
+
+/// What the video sections fall back to for the pacing keys they don't set.
+pub const PACING: Pacing = Pacing {
+    mode: PacingMode::Pixels,
+    frames_per_second: 120,
+    pixels_per_frame: 10,
+    min_seconds_between_frames: 10,
+    seconds_per_frame: 60,
+};
+/// Ages only show after a while, so the agemap can take bigger steps.
+pub const AGEMAP_PACING: Pacing = Pacing {
+    pixels_per_frame: 20,
+    min_seconds_between_frames: 20,
+    ..PACING
+};
+
+/// The pacing keys of every video section, flattened into it. They are optional as a
+/// flattened struct can't take its defaults from the section it is in.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct PacingConfig {
+    pub pacing: Option<PacingMode>,
+    pub frames_per_second: Option<u8>,
+    pub pixels_per_frame: Option<i32>,
+    pub min_seconds_between_frames: Option<i64>,
+    pub seconds_per_frame: Option<i64>,
+}
+
+impl PacingConfig {
+    pub fn pacing(&self, defaults: Pacing) -> Pacing {
+        Pacing {
+            mode: self.pacing.unwrap_or(defaults.mode),
+            frames_per_second: self.frames_per_second.unwrap_or(defaults.frames_per_second),
+            pixels_per_frame: self.pixels_per_frame.unwrap_or(defaults.pixels_per_frame),
+            min_seconds_between_frames: self
+                .min_seconds_between_frames
+                .unwrap_or(defaults.min_seconds_between_frames),
+            seconds_per_frame: self.seconds_per_frame.unwrap_or(defaults.seconds_per_frame),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimelapseConfig {
+    #[serde(flatten)]
+    pub pacing: PacingConfig,
+    pub leaderboard: Option<LeaderboardMode>,
+    pub leaderboard_size: usize,
+    pub highlight: Option<HighlightStyle>,
+    pub highlight_frames: u32,
+    pub highlight_colour: String,
+    pub mark_wipes: bool,
+    pub wipe_colour: String,
+}
+
+impl Default for TimelapseConfig {
+    fn default() -> Self {
+        TimelapseConfig {
+            pacing: PacingConfig::default(),
+            leaderboard: None,
+            leaderboard_size: 10,
+            highlight: None,
+            highlight_frames: 8,
+            highlight_colour: "#FFFFFF".to_string(),
+            mark_wipes: false,
+            wipe_colour: "#FF0000".to_string(),
+        }
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VirginmapConfig {
+    #[serde(flatten)]
+    pub pacing: PacingConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgemapConfig {
+    #[serde(flatten)]
+    pub pacing: PacingConfig,
+    /// Durations like `1h`, the half-life only for exp falloff.
+    pub lifetime: String,
+    pub half_life: Option<String>,
+    /// Exp with a half-life, linear without, by default.
+    pub falloff: Option<Falloff>,
+    pub legend: bool,
+}
+
+impl Default for AgemapConfig {
+    fn default() -> Self {
+        AgemapConfig {
+            pacing: PacingConfig::default(),
+            lifetime: "1h".to_string(),
+            half_life: None,
+            falloff: None,
+            legend: true,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompositeConfig {
+    #[serde(flatten)]
+    pub pacing: PacingConfig,
+    pub views: Vec<CompositeView>,
+    /// As square as it gets by default.
+    pub columns: Option<u32>,
+}
+
+impl Default for CompositeConfig {
+    fn default() -> Self {
+        CompositeConfig {
+            pacing: PacingConfig::default(),
+            views: vec![
+                CompositeView::Normal,
+                CompositeView::Virgin,
+                CompositeView::Age,
+                CompositeView::Heat,
+                CompositeView::Usermap,
+            ],
+            columns: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeatmapConfig {
+    pub max_heat: i32,
+}
+
+impl Default for HeatmapConfig {
+    fn default() -> Self {
+        HeatmapConfig { max_heat: 20 }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LongsessionConfig {
+    pub seconds: i64,
+    pub min_pixels: i32,
+}
+
+impl Default for LongsessionConfig {
+    fn default() -> Self {
+        LongsessionConfig {
+            seconds: 5,
+            min_pixels: 50,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaincontributorsConfig {
+    pub percentage: i32,
+}
+
+impl Default for MaincontributorsConfig {
+    fn default() -> Self {
+        MaincontributorsConfig { percentage: 90 }
+    }
+}
+
+impl Config {
+    /// Reads the given config file, or `canvas.toml` if it exists.
+    pub fn load(path: Option<&String>) -> Self {
+        let path = match path {
+            Some(path) => Path::new(path),
+            None if Path::new(DEFAULT_CONFIG).exists() => Path::new(DEFAULT_CONFIG),
+            None => return Config::default(),
+        };
+        let text = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Failed to read {}: {e}", path.display()));
+        let config: Config =
+            toml::from_str(&text).unwrap_or_else(|e| panic!("Invalid {}: {e}", path.display()));
+        for hex in &config.palette {
+            assert!(
+                hex.len() == 7 && hex.starts_with('#') && hex[1..].chars().all(|c| c.is_ascii_hexdigit()),
+                "invalid palette colour '{hex}'"
+            );
+        }
+        config
+    }
+
+    pub fn start(&self) -> i64 {
+        parse_timestamp(&self.start)
+            .unwrap_or_else(|| panic!("invalid event start '{}'", self.start))
+    }
+
+    pub fn palette(&self) -> Vec<Rgba<u8>> {
+        self.palette.iter().map(|hex| hex_to_rgba(hex)).collect()
+    }
+
+    pub fn end(&self) -> Option<i64> {
+        self.end.as_ref().map(|end| {
+            parse_timestamp(end).unwrap_or_else(|| panic!("invalid event end '{end}'"))
+        })
+    }
+}
+
+/// Everything the modules need to know about the event they are visualising.
+pub struct Event {
+    pub name: String,
+    pub window: TimeWindow,
+    pub schedule: ExpansionSchedule,
+    pub mod_actions: ModActions,
+    /// The event start, which the window can be a slice of.
+    pub start: i64,
+    /// Only set when the index matches the source, the window start and the canvas.
+    pub keyframes: Option<Keyframes>,
+    pub output: PathBuf,
+}
+
+impl Event {
+    pub fn output_path(&self, filename: &str) -> PathBuf {
+        self.output.join(filename)
+    }
+}
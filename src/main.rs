@@ -1,35 +1,441 @@
 mod config;
 mod modules;
+mod source;
 mod util;
 
+use crate::config::{Config, Event, AGEMAP_PACING, PACING};
+use crate::modules::agemap::{AgeModel, Falloff};
+use crate::modules::composite::CompositeView;
 use crate::modules::{
-    currentpixels, heatmap, longsession, singleplace, singleplayer, timelapse, usermap, virginmap, maincontributors, agemap
+    check, composite, currentpixels, generate, heatmap, longsession, singleplace, singleplayer, snapshot, timelapse, usermap, virginmap, maincontributors, agemap, merge
 };
+use crate::source::cache::CacheSource;
+use crate::source::filtered::FilteredSource;
+use crate::source::csv::CsvSource;
+use crate::source::ndjson::NdjsonSource;
+use crate::source::postgres::PostgresSource;
+use crate::source::sqlite::SqliteSource;
+use crate::source::{PlacementSource, SourceKind};
+use crate::util::color::{hex_to_rgba, parse_hex};
+use crate::util::encoder::{Encoding, VideoFormat};
+use crate::util::camera::{parse_region, Camera};
+use crate::util::expansion::{Expansion, ExpansionSchedule};
+use crate::util::highlight::{Highlight, HighlightStyle};
+use crate::util::keyframes::Keyframes;
+use crate::util::leaderboard::{Leaderboard, LeaderboardMode};
+use crate::util::render::{parse_resolution, FrameSize, Pacing, PacingMode};
+use crate::util::replay::ModActions;
+use crate::util::time::{parse_duration, parse_time_arg, resolve_window, TimeArg};
 
-use clap::{Arg, Command};
-use sqlx::SqlitePool;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use futures_util::StreamExt;
+use image::Rgba;
+use std::collections::HashSet;
 use std::env;
 use std::fs::create_dir_all;
+use std::path::PathBuf;
+
+/// For the renderers that can show the canvas as if some users never placed.
+fn exclusion_args() -> [Arg; 2] {
+    [
+        Arg::new("without-banned")
+            .long("without-banned")
+            .action(ArgAction::SetTrue)
+            .help("Replay as if banned users never placed, showing what was underneath their pixels"),
+        Arg::new("without")
+            .long("without")
+            .value_delimiter(',')
+            .num_args(1..)
+            .help("Replay as if these users never placed, by discord id or name"),
+    ]
+}
+
+/// For the video renderers, overriding their section of the event config.
+fn encoding_args() -> [Arg; 8] {
+    [
+        Arg::new("format")
+            .short('f')
+            .long("format")
+            .help("Specify the video format, only mp4 and webm need ffmpeg, y4m and raw go to stdout")
+            .value_parser(clap::value_parser!(VideoFormat)),
+        Arg::new("quality")
+            .short('q')
+            .long("quality")
+            .help("Specify the ffmpeg CRF for mp4 and webm, lower is better")
+            .value_parser(clap::value_parser!(u8)),
+        Arg::new("preset")
+            .long("preset")
+            .help("Specify the x264 preset for mp4, like veryslow or fast"),
+        Arg::new("padding")
+            .long("padding")
+            .help("Specify the border around the largest canvas, in canvas pixels")
+            .value_parser(clap::value_parser!(u32)),
+        Arg::new("scale")
+            .long("scale")
+            .help("Specify how many video pixels each canvas pixel becomes, defaults to 2 or the largest that fits the resolution")
+            .value_parser(clap::value_parser!(u32).range(1..)),
+        Arg::new("resolution")
+            .long("resolution")
+            .help("Specify the video size, like 1920x1080, 720p, 1080p, 1440p, 4k or square")
+            .value_parser(parse_resolution),
+        Arg::new("hud")
+            .long("hud")
+            .action(ArgAction::SetTrue)
+            .help("Show the time, placements, active users and canvas size in the corner"),
+        Arg::new("active-minutes")
+            .long("active-minutes")
+            .help("Specify how recently users placed to count as active on the HUD")
+            .value_parser(clap::value_parser!(i64).range(1..)),
+    ]
+}
+
+fn camera_args() -> [Arg; 5] {
+    [
+        Arg::new("region")
+            .long("region")
+            .help("Only show the part of the canvas between two corners, like 100,50,299,149")
+            .value_parser(parse_region),
+        Arg::new("camera")
+            .long("camera")
+            .help("Move the camera along the keyframes in this file")
+            .value_parser(clap::value_parser!(PathBuf)),
+        Arg::new("follow")
+            .long("follow")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("camera")
+            .help("Move the camera to wherever the most was placed lately, zooming in and out to fit it"),
+        Arg::new("follow-minutes")
+            .long("follow-minutes")
+            .help("Specify how many minutes of placements the follow camera looks at")
+            .value_parser(clap::value_parser!(i64).range(1..)),
+        Arg::new("max-zoom")
+            .long("max-zoom")
+            .help("Specify how far the follow camera zooms in at most")
+            .value_parser(clap::value_parser!(f64)),
+    ]
+}
+
+fn age_args() -> [Arg; 4] {
+    [
+        Arg::new("lifetime")
+            .short('l')
+            .long("lifetime")
+            .help("Specify how long a pixel takes to fade out, like 30m or 2h")
+            .value_parser(parse_duration),
+        Arg::new("half-life")
+            .long("half-life")
+            .help("Specify how long a pixel takes to fade to half, for exp falloff, which it picks when not set")
+            .value_parser(parse_duration),
+        Arg::new("falloff")
+            .long("falloff")
+            .help("Fade pixels evenly, quickly at first (log), or by half every half-life (exp)")
+            .value_parser(clap::value_parser!(Falloff)),
+        Arg::new("no-legend")
+            .long("no-legend")
+            .action(ArgAction::SetTrue)
+            .help("Don't show what each colour means in the corner"),
+    ]
+}
+
+fn pacing_args() -> [Arg; 6] {
+    [
+        Arg::new("pacing")
+            .long("pacing")
+            .help("Write a frame every X pixels, every X seconds of the event, or adaptive to activity")
+            .value_parser(clap::value_parser!(PacingMode)),
+        Arg::new("fps")
+            .long("fps")
+            .help("Specify the frames per second of the video")
+            .value_parser(clap::value_parser!(u8)),
+        Arg::new("pixels-per-frame")
+            .long("pixels-per-frame")
+            .help("Specify the placements per frame, for pixels and adaptive pacing")
+            .value_parser(clap::value_parser!(i32)),
+        Arg::new("min-seconds-between-frames")
+            .long("min-seconds-between-frames")
+            .help("Specify the event time a frame covers at least, for pixels pacing")
+            .value_parser(parse_duration),
+        Arg::new("seconds-per-frame")
+            .long("seconds-per-frame")
+            .help("Specify the event time per frame, like 30s or 5m, for time and adaptive pacing")
+            .value_parser(parse_duration),
+        Arg::new("duration")
+            .short('d')
+            .long("duration")
+            .help("Make a video of about this length, like 2m, working out the frames per pixels or seconds")
+            .value_parser(parse_duration),
+    ]
+}
 
 fn cli() -> Command {
     Command::new("canvas")
         .about("canvas tools !!")
         .subcommand_required(true)
         .arg_required_else_help(true)
-        .subcommand(Command::new("timelapse").about("Render a timelapse video of the canvas"))
-        .subcommand(Command::new("virginmap").about("Render a timelapse video of the canvas"))
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .global(true)
+                .help("Event config file, defaults to canvas.toml if it exists"),
+        )
+        .arg(
+            Arg::new("database")
+                .long("database")
+                .global(true)
+                .help("Database url (sqlite or postgres) or csv/ndjson file, overrides the event config and DATABASE_URL"),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .global(true)
+                .help("Output directory, overrides the event config")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("since")
+                .long("since")
+                .global(true)
+                .allow_hyphen_values(true)
+                .help("Start of the time window, as a timestamp or a duration since the event start")
+                .value_parser(parse_time_arg),
+        )
+        .arg(
+            Arg::new("until")
+                .long("until")
+                .global(true)
+                .allow_hyphen_values(true)
+                .help("End of the time window, as a timestamp or a duration since the event start")
+                .value_parser(parse_time_arg),
+        )
+        .arg(
+            Arg::new("mod-actions")
+                .long("mod-actions")
+                .global(true)
+                .help("Show moderator actions as a wipe in their colour, or as a rollback to the placement before, overrides the event config")
+                .value_parser(clap::value_parser!(ModActions)),
+        )
+        .arg(
+            Arg::new("no-cache")
+                .long("no-cache")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Read the database even if there is an up to date cache"),
+        )
+        .subcommand(
+            Command::new("cache")
+                .about("Compile the database into a binary cache, used by every other subcommand"),
+        )
+        .subcommand(
+            Command::new("keyframes")
+                .about("Index the canvas state every so often, so commands can start replaying from there")
+                .arg(
+                    Arg::new("interval")
+                        .short('i')
+                        .long("interval")
+                        .help("Specify the time between keyframes, like 1h or 30m")
+                        .default_value("1h")
+                        .value_parser(parse_duration),
+                ),
+        )
+        .subcommand(
+            Command::new("check")
+                .about("Check the database for problems, exiting with an error code if there are any"),
+        )
+        .subcommand(
+            Command::new("generate")
+                .about("Generate a made up event database, for demos and testing")
+                .arg(
+                    Arg::new("destination")
+                        .required(true)
+                        .help("The database to create")
+                        .value_parser(clap::value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("users")
+                        .short('u')
+                        .long("users")
+                        .help("Specify the amount of users")
+                        .default_value("200")
+                        .value_parser(clap::value_parser!(u32)),
+                )
+                .arg(
+                    Arg::new("duration")
+                        .short('d')
+                        .long("duration")
+                        .help("Specify how long the event lasts, like 1d or 12h")
+                        .default_value("3d")
+                        .value_parser(parse_duration),
+                )
+                .arg(
+                    Arg::new("cooldown")
+                        .long("cooldown")
+                        .help("Specify the seconds between placements of a user")
+                        .default_value("30")
+                        .value_parser(clap::value_parser!(i64).range(1..)),
+                )
+                .arg(
+                    Arg::new("artworks")
+                        .short('a')
+                        .long("artworks")
+                        .help("Specify the amount of artworks users work on")
+                        .default_value("40")
+                        .value_parser(clap::value_parser!(u32)),
+                )
+                .arg(
+                    Arg::new("banned")
+                        .short('b')
+                        .long("banned")
+                        .help("Specify the amount of banned users, who grief the canvas")
+                        .default_value("5")
+                        .value_parser(clap::value_parser!(u32)),
+                )
+                .arg(
+                    Arg::new("mod-actions")
+                        .short('m')
+                        .long("mod-actions")
+                        .help("Specify the amount of areas wiped by a moderator")
+                        .default_value("10")
+                        .value_parser(clap::value_parser!(u32)),
+                )
+                .arg(
+                    Arg::new("seed")
+                        .short('s')
+                        .long("seed")
+                        .help("Specify the random seed, the same seed gives the same database")
+                        .default_value("0")
+                        .value_parser(clap::value_parser!(u64)),
+                ),
+        )
+        .subcommand(
+            Command::new("merge")
+                .about("Merge several databases of the same event into a new database")
+                .arg(
+                    Arg::new("destination")
+                        .required(true)
+                        .help("The database to create")
+                        .value_parser(clap::value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("databases")
+                        .required(true)
+                        .num_args(2..)
+                        .help("The databases to merge, the first one wins conflicts")
+                        .value_parser(clap::value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            Command::new("timelapse")
+                .about("Render a timelapse video of the canvas")
+                .args(exclusion_args())
+                .args(pacing_args())
+                .args(encoding_args())
+                .args(camera_args())
+                .arg(
+                    Arg::new("leaderboard")
+                        .long("leaderboard")
+                        .help("Show the top placers next to the canvas, by placements so far or by pixels on the canvas")
+                        .value_parser(clap::value_parser!(LeaderboardMode)),
+                )
+                .arg(
+                    Arg::new("leaderboard-size")
+                        .long("leaderboard-size")
+                        .help("Specify how many users the leaderboard shows")
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("highlight")
+                        .long("highlight")
+                        .help("Light up new pixels for a few frames, as a flash, an outline around them or a glow")
+                        .value_parser(clap::value_parser!(HighlightStyle)),
+                )
+                .arg(
+                    Arg::new("highlight-frames")
+                        .long("highlight-frames")
+                        .help("Specify how many frames the highlights take to fade out")
+                        .value_parser(clap::value_parser!(u32).range(1..)),
+                )
+                .arg(
+                    Arg::new("highlight-colour")
+                        .long("highlight-colour")
+                        .help("Specify the colour new pixels light up in, like #FFFFFF")
+                        .value_parser(parse_colour),
+                )
+                .arg(
+                    Arg::new("mark-wipes")
+                        .long("mark-wipes")
+                        .action(ArgAction::SetTrue)
+                        .help("Light up pixels wiped by moderators, in their own colour"),
+                )
+                .arg(
+                    Arg::new("wipe-colour")
+                        .long("wipe-colour")
+                        .help("Specify the colour wiped pixels light up in, like #FF0000")
+                        .value_parser(parse_colour),
+                ),
+        )
+        .subcommand(
+            Command::new("virginmap")
+                .about("Render a timelapse video of the canvas")
+                .args(pacing_args())
+                .args(encoding_args())
+                .args(camera_args()),
+        )
         .subcommand(
             Command::new("agemap")
-                .about("Render a timelapse showing the age of each pixel"),
+                .about("Render a timelapse showing the age of each pixel")
+                .args(pacing_args())
+                .args(encoding_args())
+                .args(camera_args())
+                .args(age_args()),
+        )
+        .subcommand(
+            Command::new("composite")
+                .about("Render several views side by side in one video, from a single replay")
+                .args(pacing_args())
+                .args(encoding_args())
+                .args(age_args())
+                .arg(
+                    Arg::new("views")
+                        .long("views")
+                        .help("Specify the views to show, like normal,virgin,age,heat,usermap")
+                        .value_delimiter(',')
+                        .num_args(1..)
+                        .value_parser(clap::value_parser!(CompositeView)),
+                )
+                .arg(
+                    Arg::new("columns")
+                        .long("columns")
+                        .help("Specify how many views are next to each other, defaults to as square as it gets")
+                        .value_parser(clap::value_parser!(u32).range(1..)),
+                ),
+        )
+        .subcommand(
+            Command::new("heatmap")
+                .about("Render a heatmap of the canvas")
+                .arg(
+                    Arg::new("max-heat")
+                        .short('m')
+                        .long("max-heat")
+                        .help("Specify the amount of placements shown as the hottest colour")
+                        .value_parser(clap::value_parser!(i32)),
+                ),
+        )
+        .subcommand(
+            Command::new("snapshot")
+                .about("Render the canvas as it was at the end of the time window")
+                .args(exclusion_args()),
         )
-        .subcommand(Command::new("heatmap").about("Render a heatmap of the canvas"))
         .subcommand(
             Command::new("usermap")
-                .about("Render a usermap of the canvas, showing who placed each pixel"),
+                .about("Render a usermap of the canvas, showing who placed each pixel")
+                .args(exclusion_args()),
         )
         .subcommand(
             Command::new("singleplace")
-                .about("Render the canvas, without placing pixels over drawn pixels"),
+                .about("Render the canvas, without placing pixels over drawn pixels")
+                .args(exclusion_args()),
         )
         .subcommand(
             Command::new("singleplayer")
@@ -43,7 +449,13 @@ fn cli() -> Command {
                         .short('s')
                         .long("seconds")
                         .help("Specify the amount of seconds")
-                        .default_value("5")
+                        .value_parser(clap::value_parser!(i64)),
+                )
+                .arg(
+                    Arg::new("min-pixels")
+                        .short('m')
+                        .long("min-pixels")
+                        .help("Specify the amount of pixels a session needs to be listed")
                         .value_parser(clap::value_parser!(i32)),
                 ),
         )
@@ -59,7 +471,6 @@ fn cli() -> Command {
                         .short('p')
                         .long("percentage")
                         .help("Specify the percentage")
-                        .default_value("90")
                         .value_parser(clap::value_parser!(i32)),
                 ),
         )
@@ -69,74 +480,537 @@ fn cli() -> Command {
 async fn main() {
     let matches = cli().get_matches();
     dotenvy::dotenv().ok();
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let pool = SqlitePool::connect(&database_url).await.unwrap();
-    create_dir_all("./output").expect("Failed to create output directory");
+    if let Some(("merge", sub_matches)) = matches.subcommand() {
+        let databases: Vec<PathBuf> = sub_matches
+            .get_many::<PathBuf>("databases")
+            .unwrap()
+            .cloned()
+            .collect();
+        merge::merge(sub_matches.get_one::<PathBuf>("destination").unwrap(), &databases).await;
+        return;
+    }
+    let config = Config::load(matches.get_one::<String>("config"));
+    if let Some(("generate", sub_matches)) = matches.subcommand() {
+        let settings = generate::Settings {
+            users: *sub_matches.get_one::<u32>("users").unwrap(),
+            duration: *sub_matches.get_one::<i64>("duration").unwrap(),
+            cooldown: *sub_matches.get_one::<i64>("cooldown").unwrap(),
+            artworks: *sub_matches.get_one::<u32>("artworks").unwrap(),
+            banned: *sub_matches.get_one::<u32>("banned").unwrap(),
+            mod_actions: *sub_matches.get_one::<u32>("mod-actions").unwrap(),
+            seed: *sub_matches.get_one::<u64>("seed").unwrap(),
+        };
+        generate::generate(
+            sub_matches.get_one::<PathBuf>("destination").unwrap(),
+            &settings,
+            config.start(),
+            &config.palette,
+            &config.expansions,
+        )
+        .await;
+        return;
+    }
+    let database_url = matches
+        .get_one::<String>("database")
+        .cloned()
+        .or(config.database.clone())
+        .or(env::var("DATABASE_URL").ok())
+        .expect("Set a database with --database, in canvas.toml or as DATABASE_URL");
+
+    let kind = SourceKind::parse(&database_url);
+    let paths = Paths {
+        cache: kind.sidecar_path("cache"),
+        keyframes: kind.sidecar_path("keyframes"),
+    };
+    match kind {
+        SourceKind::Sqlite(url) => {
+            open(SqliteSource::connect(&url).await, paths, &matches, &config).await
+        }
+        SourceKind::Postgres(url) => {
+            open(PostgresSource::connect(&url).await, paths, &matches, &config).await
+        }
+        SourceKind::Csv(path) => open(CsvSource::open(path), paths, &matches, &config).await,
+        SourceKind::Ndjson(path) => open(NdjsonSource::open(path), paths, &matches, &config).await,
+    }
+}
+
+/// Files kept next to the source, `None` for in-memory databases.
+struct Paths {
+    cache: Option<PathBuf>,
+    keyframes: Option<PathBuf>,
+}
+
+/// Writes the cache, or runs the subcommand on the cache instead of the source when it is up to date.
+async fn open(
+    source: impl PlacementSource,
+    paths: Paths,
+    matches: &ArgMatches,
+    config: &Config,
+) {
+    if let Some(("cache", _sub_matches)) = matches.subcommand() {
+        let cache_path = paths.cache.expect("Can't cache an in-memory database");
+        CacheSource::write(&source, &cache_path, &config.palette()).await;
+        return;
+    }
+
+    // Checking the cache would hide what is wrong with the database.
+    let cache = paths
+        .cache
+        .filter(|_| !matches.get_flag("no-cache") && matches.subcommand_name() != Some("check"))
+        .and_then(CacheSource::open);
+    match cache {
+        Some(cache) if cache.fingerprint().await == source.fingerprint().await => {
+            run(cache, paths.keyframes, matches, config).await
+        }
+        Some(_) => {
+            eprintln!("The cache is out of date, reading the database. Run `canvas cache` to update it");
+            run(source, paths.keyframes, matches, config).await
+        }
+        None => run(source, paths.keyframes, matches, config).await,
+    }
+}
+
+/// Leaves out the users passed with `--without-banned` or `--without`, if any.
+async fn run(
+    source: impl PlacementSource,
+    keyframes_path: Option<PathBuf>,
+    matches: &ArgMatches,
+    config: &Config,
+) {
+    let excluded = match matches.subcommand() {
+        Some((_, sub_matches)) => excluded_users(&source, sub_matches).await,
+        None => HashSet::new(),
+    };
+    if excluded.is_empty() {
+        execute(source, keyframes_path, matches, config).await
+    } else {
+        // The keyframes have the excluded users' pixels in them.
+        eprintln!("Leaving out the placements of {} users", excluded.len());
+        let mod_actions = matches
+            .get_one::<ModActions>("mod-actions")
+            .copied()
+            .unwrap_or(config.mod_actions);
+        execute(FilteredSource::new(source, excluded, mod_actions), None, matches, config).await
+    }
+}
+
+async fn excluded_users(source: &impl PlacementSource, sub_matches: &ArgMatches) -> HashSet<u64> {
+    let without_banned = matches!(sub_matches.try_get_one::<bool>("without-banned"), Ok(Some(true)));
+    let without: Vec<String> = match sub_matches.try_get_many::<String>("without") {
+        Ok(Some(values)) => values.cloned().collect(),
+        _ => vec![],
+    };
+    if !without_banned && without.is_empty() {
+        return HashSet::new();
+    }
+
+    let users = source.users().await;
+    let mut excluded: HashSet<u64> = users
+        .iter()
+        .filter(|(_, user)| without_banned && user.banned)
+        .map(|(id, _)| *id)
+        .collect();
+    for user in without {
+        let id = user.parse::<u64>().ok().or_else(|| {
+            users
+                .iter()
+                .find(|(_, known)| known.name == user)
+                .map(|(id, _)| *id)
+        });
+        excluded.insert(id.unwrap_or_else(|| panic!("Unknown user '{user}'")));
+    }
+    excluded
+}
+
+async fn execute(
+    source: impl PlacementSource,
+    keyframes_path: Option<PathBuf>,
+    matches: &ArgMatches,
+    config: &Config,
+) {
+    let output = matches
+        .get_one::<PathBuf>("output")
+        .cloned()
+        .unwrap_or(config.output.clone());
+    create_dir_all(&output).expect("Failed to create output directory");
+    let window = resolve_window(
+        &source,
+        config.start(),
+        config.end(),
+        matches.get_one::<TimeArg>("since").copied(),
+        matches.get_one::<TimeArg>("until").copied(),
+    )
+    .await;
+
+    // Inferring the expansions needs readable colours, which is one of the things `check` looks for.
+    if let Some(("check", _sub_matches)) = matches.subcommand() {
+        let schedule = (!config.expansions.is_empty())
+            .then(|| ExpansionSchedule::configured(&config.expansions));
+        check::check(&source, &window, schedule.as_ref(), &config.palette(), &output).await;
+        return;
+    }
+    let schedule = ExpansionSchedule::load(&source, &config.expansions, config.start()).await;
+
+    if let Some(("keyframes", sub_matches)) = matches.subcommand() {
+        let keyframes_path = keyframes_path.expect("Can't index an in-memory database");
+        let interval = *sub_matches.get_one::<i64>("interval").unwrap();
+        Keyframes::write(&source, &schedule, &window, &keyframes_path, interval).await;
+        return;
+    }
+    let keyframes = match keyframes_path.and_then(Keyframes::open) {
+        Some(keyframes) if keyframes.matches(&source.fingerprint().await, window.since, &schedule) => {
+            Some(keyframes)
+        }
+        Some(_) => {
+            eprintln!("The keyframes are out of date, replaying from the start. Run `canvas keyframes` to update them");
+            None
+        }
+        None => None,
+    };
+    let event = Event {
+        name: config.name.clone(),
+        window,
+        schedule,
+        mod_actions: matches
+            .get_one::<ModActions>("mod-actions")
+            .copied()
+            .unwrap_or(config.mod_actions),
+        start: config.start(),
+        keyframes,
+        output,
+    };
 
     match matches.subcommand() {
-        Some(("timelapse", _sub_matches)) => {
-            // TODO: let configure on cmdline.
-            let pixels_per_frame = 10;
-            let min_seconds_between_frames = 10;
-            let frames_per_second = 120;
-
-            timelapse::timelapse(
-                pool,
-                frames_per_second,
-                pixels_per_frame,
-                min_seconds_between_frames,
-            )
-            .await;
+        Some(("timelapse", sub_matches)) => {
+            let pacing = pacing(&source, &event, config.timelapse.pacing.pacing(PACING), sub_matches).await;
+            let leaderboard = sub_matches
+                .get_one::<LeaderboardMode>("leaderboard")
+                .copied()
+                .or(config.timelapse.leaderboard)
+                .map(|mode| {
+                    let size = sub_matches
+                        .get_one::<usize>("leaderboard-size")
+                        .copied()
+                        .unwrap_or(config.timelapse.leaderboard_size);
+                    (mode, size)
+                });
+            let sidebar = if leaderboard.is_some() { Leaderboard::WIDTH } else { 0 };
+            let encoding = encoding(&source, config, sub_matches, &event, sidebar).await;
+            let highlight = highlight(config, sub_matches);
+            timelapse::timelapse(&source, &event, &pacing, &encoding, leaderboard, highlight).await;
         }
-        Some(("virginmap", _sub_matches)) => {
-            // TODO: let configure on cmdline.
-            let pixels_per_frame = 10;
-            let min_seconds_between_frames = 10;
-            let frames_per_second = 120;
-
-            virginmap::timelapse(
-                pool,
-                frames_per_second,
-                pixels_per_frame,
-                min_seconds_between_frames,
-            )
-            .await;
+        Some(("virginmap", sub_matches)) => {
+            let pacing = pacing(&source, &event, config.virginmap.pacing.pacing(PACING), sub_matches).await;
+            let encoding = encoding(&source, config, sub_matches, &event, 0).await;
+            virginmap::timelapse(&source, &event, &pacing, &encoding).await;
+        }
+        Some(("agemap", sub_matches)) => {
+            let pacing = pacing(&source, &event, config.agemap.pacing.pacing(AGEMAP_PACING), sub_matches).await;
+            let encoding = encoding(&source, config, sub_matches, &event, 0).await;
+            let legend = !sub_matches.get_flag("no-legend") && config.agemap.legend;
+            agemap::agemap(&source, &event, &pacing, &encoding, age_model(config, sub_matches), legend).await;
         }
-        Some(("agemap", _sub_matches)) => {
-            // TODO: let configure on cmdline.
-            let pixels_per_frame = 20;
-            let min_seconds_between_frames = 20;
-            let frames_per_second = 120;
-
-            agemap::agemap(
-                pool,
-                frames_per_second,
-                pixels_per_frame,
-                min_seconds_between_frames,
+        Some(("composite", sub_matches)) => {
+            let pacing = pacing(&source, &event, config.composite.pacing.pacing(PACING), sub_matches).await;
+            let views = sub_matches
+                .get_many::<CompositeView>("views")
+                .map(|views| views.copied().collect::<Vec<_>>())
+                .unwrap_or(config.composite.views.clone());
+            assert!(!views.is_empty(), "the composite needs at least one view");
+            let columns = composite::columns(
+                views.len(),
+                sub_matches.get_one::<u32>("columns").copied().or(config.composite.columns),
+            );
+            let (encoding, tile) = composite_encoding(config, sub_matches, &event, views.len() as u32, columns);
+            let layout = composite::Layout {
+                views,
+                columns,
+                tile,
+                legend: !sub_matches.get_flag("no-legend") && config.agemap.legend,
+            };
+            composite::composite(
+                &source,
+                &event,
+                &pacing,
+                &encoding,
+                &layout,
+                age_model(config, sub_matches),
+                config.heatmap.max_heat,
             )
             .await;
         }
-        Some(("heatmap", _sub_matches)) => {
-            heatmap::heatmap(pool).await;
+        Some(("heatmap", sub_matches)) => {
+            let max_heat = sub_matches
+                .get_one::<i32>("max-heat")
+                .copied()
+                .unwrap_or(config.heatmap.max_heat);
+            heatmap::heatmap(&source, &event, max_heat).await;
+        }
+        Some(("snapshot", _sub_matches)) => {
+            snapshot::snapshot(&source, &event).await;
         }
         Some(("usermap", _sub_matches)) => {
-            usermap::usermap(pool).await;
+            usermap::usermap(&source, &event).await;
         }
         Some(("singleplace", _sub_matches)) => {
-            singleplace::singleplace(pool).await;
+            singleplace::singleplace(&source, &event).await;
         }
         Some(("singleplayer", _sub_matches)) => {
-            singleplayer::singleplayer(pool).await;
+            singleplayer::singleplayer(&source, &event).await;
         }
         Some(("longsession", sub_matches)) => {
-            longsession::longsession(pool, sub_matches.get_one::<i32>("seconds").unwrap()).await;
+            let seconds = sub_matches
+                .get_one::<i64>("seconds")
+                .copied()
+                .unwrap_or(config.longsession.seconds);
+            let min_pixels = sub_matches
+                .get_one::<i32>("min-pixels")
+                .copied()
+                .unwrap_or(config.longsession.min_pixels);
+            longsession::longsession(&source, &event, seconds, min_pixels).await;
         }
         Some(("currentpixels", _sub_matches)) => {
-            currentpixels::currentpixels(pool).await;
+            currentpixels::currentpixels(&source, &event).await;
         }
         Some(("maincontributors", sub_matches)) => {
-            maincontributors::maincontributors(pool, sub_matches.get_one::<i32>("percentage").unwrap()).await;
+            let percentage = sub_matches
+                .get_one::<i32>("percentage")
+                .copied()
+                .unwrap_or(config.maincontributors.percentage);
+            maincontributors::maincontributors(&source, &event, percentage).await;
         }
         _ => unreachable!(),
     }
 }
+
+/// The pacing from the event config with the command line on top, fitted to `--duration` if it is set.
+async fn pacing(
+    source: &impl PlacementSource,
+    event: &Event,
+    mut pacing: Pacing,
+    sub_matches: &ArgMatches,
+) -> Pacing {
+    if let Some(mode) = sub_matches.get_one::<PacingMode>("pacing") {
+        pacing.mode = *mode;
+    }
+    if let Some(fps) = sub_matches.get_one::<u8>("fps") {
+        pacing.frames_per_second = *fps;
+    }
+    if let Some(pixels) = sub_matches.get_one::<i32>("pixels-per-frame") {
+        pacing.pixels_per_frame = *pixels;
+    }
+    if let Some(seconds) = sub_matches.get_one::<i64>("min-seconds-between-frames") {
+        pacing.min_seconds_between_frames = *seconds;
+    }
+    if let Some(seconds) = sub_matches.get_one::<i64>("seconds-per-frame") {
+        pacing.seconds_per_frame = *seconds;
+    }
+    assert!(pacing.frames_per_second > 0, "the video needs at least one frame per second");
+    assert!(pacing.pixels_per_frame > 0, "a frame needs at least one pixel");
+    assert!(pacing.seconds_per_frame > 0, "a frame needs to cover at least a second");
+
+    if let Some(duration) = sub_matches.get_one::<i64>("duration") {
+        let until = match event.window.until {
+            Some(until) => until,
+            None => source.last_placement().await.unwrap_or(event.window.since),
+        };
+        // Counting the placements means reading them all, so only when they are needed.
+        let placements = match pacing.mode {
+            PacingMode::Time => 0,
+            PacingMode::Pixels | PacingMode::Adaptive => {
+                source.placements(&event.window).count().await as u64
+            }
+        };
+        pacing.fit(*duration, until - event.window.since, placements);
+        let per_frame = match pacing.mode {
+            PacingMode::Pixels => format!("{} pixels", pacing.pixels_per_frame),
+            PacingMode::Time => format!("{}s", pacing.seconds_per_frame),
+            PacingMode::Adaptive => {
+                format!("{} pixels or {}s", pacing.pixels_per_frame, pacing.seconds_per_frame)
+            }
+        };
+        eprintln!("Pacing for a {duration}s video: {per_frame} per frame");
+    }
+    pacing
+}
+
+/// How the agemap fades pixels, from the agemap section of the event config with the command line on top.
+fn age_model(config: &Config, sub_matches: &ArgMatches) -> AgeModel {
+    let duration = |text: &String| parse_duration(text).unwrap_or_else(|e| panic!("{e}"));
+    let lifetime = sub_matches
+        .get_one::<i64>("lifetime")
+        .copied()
+        .unwrap_or_else(|| duration(&config.agemap.lifetime));
+    let half_life = sub_matches
+        .get_one::<i64>("half-life")
+        .copied()
+        .or_else(|| config.agemap.half_life.as_ref().map(duration));
+    let falloff = sub_matches
+        .get_one::<Falloff>("falloff")
+        .copied()
+        .or(config.agemap.falloff)
+        .unwrap_or(if half_life.is_some() { Falloff::Exp } else { Falloff::Linear });
+    // Faded to under 1/256th by the end of the lifetime, which is as good as gone.
+    let half_life = half_life.unwrap_or(lifetime / 8);
+    assert!(lifetime > 0, "pixels need to live for at least a second");
+    assert!(half_life > 0, "the half-life needs to be at least a second");
+    AgeModel {
+        falloff,
+        lifetime,
+        half_life,
+    }
+}
+
+/// The highlights of the timelapse, if new pixels or wipes light up at all.
+fn highlight(config: &Config, sub_matches: &ArgMatches) -> Option<Highlight> {
+    let style = sub_matches.get_one::<HighlightStyle>("highlight").copied().or(config.timelapse.highlight);
+    let mark_wipes = sub_matches.get_flag("mark-wipes") || config.timelapse.mark_wipes;
+    if style.is_none() && !mark_wipes {
+        return None;
+    }
+    let frames = sub_matches
+        .get_one::<u32>("highlight-frames")
+        .copied()
+        .unwrap_or(config.timelapse.highlight_frames);
+    assert!(frames > 0, "the highlights need to last at least a frame");
+    Some(Highlight {
+        style: style.unwrap_or(HighlightStyle::Flash),
+        frames,
+        placed: style.map(|_| {
+            sub_matches
+                .get_one::<Rgba<u8>>("highlight-colour")
+                .copied()
+                .unwrap_or_else(|| hex_to_rgba(&config.timelapse.highlight_colour))
+        }),
+        wiped: mark_wipes.then(|| {
+            sub_matches
+                .get_one::<Rgba<u8>>("wipe-colour")
+                .copied()
+                .unwrap_or_else(|| hex_to_rgba(&config.timelapse.wipe_colour))
+        }),
+    })
+}
+
+fn parse_colour(text: &str) -> Result<Rgba<u8>, String> {
+    parse_hex(text).ok_or_else(|| format!("invalid colour '{text}', use #RRGGBB"))
+}
+
+fn resolution(config: &Config, sub_matches: &ArgMatches) -> Option<(u32, u32)> {
+    sub_matches.get_one::<(u32, u32)>("resolution").copied().or_else(|| {
+        config.video.resolution.as_ref().map(|resolution| {
+            parse_resolution(resolution).unwrap_or_else(|e| panic!("{e}"))
+        })
+    })
+}
+
+/// The video section of the event config with the command line on top.
+/// `sidebar` is the width of the panel next to the canvas, in font pixels.
+async fn encoding(
+    source: &impl PlacementSource,
+    config: &Config,
+    sub_matches: &ArgMatches,
+    event: &Event,
+    sidebar: u32,
+) -> Encoding {
+    let resolution = resolution(config, sub_matches);
+    let region = sub_matches.get_one::<Expansion>("region").copied().or_else(|| {
+        config.video.region.as_ref().map(|region| {
+            parse_region(region).unwrap_or_else(|e| panic!("{e}"))
+        })
+    });
+    let largest = event.schedule.largest();
+    let follow = sub_matches.get_flag("follow") || config.video.follow;
+    let camera = match (sub_matches.get_one::<PathBuf>("camera").or(config.video.camera.as_ref()), region) {
+        _ if follow => Some(Camera::follow(
+            sub_matches
+                .get_one::<i64>("follow-minutes")
+                .copied()
+                .unwrap_or(config.video.follow_minutes),
+            sub_matches.get_one::<f64>("max-zoom").copied().unwrap_or(config.video.max_zoom).max(1.0),
+        )),
+        (Some(path), _) => {
+            // Like `--since`, camera times before the end count back from the end of the window.
+            let end = match event.window.until {
+                Some(until) => until,
+                None => source.last_placement().await.unwrap_or(event.start),
+            };
+            Some(Camera::load(path, event, end))
+        }
+        (None, Some(region)) => Some(Camera::fixed(
+            region.x as f64 + region.width as f64 / 2.0,
+            region.y as f64 + region.height as f64 / 2.0,
+        )),
+        (None, None) => None,
+    };
+    let frame = FrameSize::fit(
+        region.map_or(largest, |region| (region.width, region.height)),
+        sub_matches.get_one::<u32>("padding").copied().unwrap_or(config.video.padding),
+        sidebar,
+        sub_matches.get_one::<u32>("scale").copied().or(config.video.scale),
+        resolution,
+        camera.is_some(),
+    );
+    // With a camera the canvas is drawn at its real size, and the camera picks from that.
+    let canvas = match camera {
+        Some(_) => FrameSize::fit(largest, 0, 0, Some(1), None, false),
+        None => frame,
+    };
+    video_encoding(config, sub_matches, canvas, frame, camera)
+}
+
+/// The encoding for `views` tiles in rows of `columns`, and the frame of each tile. There is no
+/// camera, each tile shows the whole canvas.
+fn composite_encoding(
+    config: &Config,
+    sub_matches: &ArgMatches,
+    event: &Event,
+    views: u32,
+    columns: u32,
+) -> (Encoding, FrameSize) {
+    let tile = FrameSize::fit(
+        event.schedule.largest(),
+        sub_matches.get_one::<u32>("padding").copied().unwrap_or(config.video.padding),
+        0,
+        Some(1),
+        None,
+        false,
+    );
+    let frame = FrameSize::fit(
+        (tile.width * columns, tile.height * views.div_ceil(columns)),
+        0,
+        0,
+        sub_matches.get_one::<u32>("scale").copied().or(config.video.scale),
+        resolution(config, sub_matches),
+        false,
+    );
+    (video_encoding(config, sub_matches, frame, frame, None), tile)
+}
+
+fn video_encoding(
+    config: &Config,
+    sub_matches: &ArgMatches,
+    canvas: FrameSize,
+    frame: FrameSize,
+    camera: Option<Camera>,
+) -> Encoding {
+    Encoding {
+        format: sub_matches
+            .get_one::<VideoFormat>("format")
+            .copied()
+            .unwrap_or(config.video.format),
+        quality: sub_matches.get_one::<u8>("quality").copied().or(config.video.quality),
+        preset: sub_matches
+            .get_one::<String>("preset")
+            .cloned()
+            .unwrap_or(config.video.preset.clone()),
+        canvas,
+        frame,
+        camera,
+        hud: (sub_matches.get_flag("hud") || config.video.hud).then(|| {
+            sub_matches
+                .get_one::<i64>("active-minutes")
+                .copied()
+                .unwrap_or(config.video.active_minutes)
+        }),
+    }
+}
@@ -1,170 +1,178 @@
-use crate::config::CANVAS_SIZES;
-use crate::util::render::{blank_image_borders, pixel_offset, start_ffmpeg};
-use image::Rgba;
-use palette::encoding::{Linear, Srgb};
-use palette::rgb::Rgb;
-use palette::{LinSrgb, Mix};
-use sqlx::{query_as, Pool, Sqlite};
-use std::io::Write;
-use std::process::ChildStdin;
-
-struct Placement {
-    pub created_at: Option<String>,
-    pub x: i64,
-    pub y: i64,
+use crate::config::Event;
+use crate::modules::heatmap::heatmap_color;
+use crate::source::{Placement, PlacementSource};
+use crate::util::encoder::Encoding;
+use crate::util::expansion::Expansion;
+use crate::util::font::{draw_text, fill, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::util::render::{blank_image_borders, pixel_offset, FrameSize, Pacing};
+use crate::util::replay::{CanvasReplay, PixelState};
+use crate::util::video::{render, Panel};
+use clap::ValueEnum;
+use image::{Pixel, Rgba, RgbaImage};
+use rayon::prelude::*;
+use serde::Deserialize;
+
+const LEGEND_BACKGROUND: Rgba<u8> = Rgba([24, 24, 24, 255]);
+const LEGEND_TEXT: Rgba<u8> = Rgba([255, 255, 255, 255]);
+/// The ages the legend shows, in quarters of the lifetime or in half-lives.
+const LEGEND_STEPS: i64 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Falloff {
+    /// Fades evenly over the lifetime.
+    Linear,
+    /// Fades quickly at first and slowly towards the end of the lifetime.
+    Log,
+    /// Halves in brightness every half-life.
+    Exp,
 }
 
-const COLORS: [(f32, Rgb<Linear<Srgb>>); 4] = [
-    (0.0, LinSrgb::new(0.0, 0.0, 0.0)),    // Black
-    (0.2, LinSrgb::new(0.5451, 0.0, 0.0)), // DarkRed
-    (0.66, LinSrgb::new(1.0, 1.0, 0.0)),   // Yellow
-    (1.0, LinSrgb::new(1.0, 1.0, 1.0)),    // White
-];
-
-fn convert_color(color: Rgb<Linear<Srgb>>) -> Rgba<u8> {
-    Rgba([
-        (color.red * 255.0) as u8,
-        (color.green * 255.0) as u8,
-        (color.blue * 255.0) as u8,
-        255,
-    ])
+/// How a pixel fades from the moment it was placed, in seconds of the event.
+#[derive(Clone, Copy, Debug)]
+pub struct AgeModel {
+    pub falloff: Falloff,
+    pub lifetime: i64,
+    pub half_life: i64,
 }
 
-fn heatmap_color(value: f32) -> Rgba<u8> {
-    for i in 0..COLORS.len() - 1 {
-        let (t1, c1) = COLORS[i];
-        let (t2, c2) = COLORS[i + 1];
+impl AgeModel {
+    /// From 1 for a pixel placed just now to 0 for one that faded out.
+    fn brightness(&self, age: i64) -> f32 {
+        let age = age.max(0) as f64;
+        let brightness = match self.falloff {
+            Falloff::Linear => 1.0 - age / self.lifetime as f64,
+            Falloff::Log => 1.0 - (1.0 + age).ln() / (1.0 + self.lifetime as f64).ln(),
+            Falloff::Exp => 0.5f64.powf(age / self.half_life as f64),
+        };
+        brightness.clamp(0.0, 1.0) as f32
+    }
 
-        if value >= t1 && value <= t2 {
-            let t = (value - t1) / (t2 - t1);
-            return convert_color(c1.mix(c2, t));
-        }
+    /// The colour of a pixel placed `age` seconds ago.
+    fn color(&self, age: i64) -> Rgba<u8> {
+        heatmap_color(self.brightness(age)).to_rgba()
     }
 
-    convert_color(COLORS.last().unwrap().1)
+    /// Draws which colour is how old, in a box with its bottom right corner at `right` and `bottom`.
+    pub fn draw_legend(&self, image: &mut RgbaImage, right: u32, bottom: u32, size: u32) {
+        let step = match self.falloff {
+            Falloff::Exp => self.half_life,
+            Falloff::Linear | Falloff::Log => self.lifetime / LEGEND_STEPS,
+        };
+        let lines = (0..=LEGEND_STEPS)
+            .map(|i| (i * step, minutes(i * step)))
+            .collect::<Vec<_>>();
+        let longest = lines.iter().map(|(_, label)| label.len()).max().unwrap() as u32;
+        let width = (2 + GLYPH_HEIGHT + 2 + longest * (GLYPH_WIDTH + 1) - 1 + 2) * size;
+        let height = (2 + (lines.len() as u32 + 1) * (GLYPH_HEIGHT + 2)) * size;
+        let (Some(left), Some(top)) = (right.checked_sub(width), bottom.checked_sub(height)) else {
+            return;
+        };
+
+        fill(image, left, top, width, height, LEGEND_BACKGROUND);
+        draw_text(image, "AGE", left + 2 * size, top + 2 * size, size, LEGEND_TEXT);
+        for (row, (age, label)) in lines.iter().enumerate() {
+            let line_top = top + (2 + (row as u32 + 1) * (GLYPH_HEIGHT + 2)) * size;
+            fill(image, left + 2 * size, line_top, GLYPH_HEIGHT * size, GLYPH_HEIGHT * size, self.color(*age));
+            draw_text(image, label, left + (2 + GLYPH_HEIGHT + 2) * size, line_top, size, LEGEND_TEXT);
+        }
+    }
 }
 
-pub async fn agemap(
-    pool: Pool<Sqlite>,
-    fps: u8,
-    pixels_per_frame: i32,
-    min_seconds_per_frame: i32,
-) {
-    let placements = query_as!(Placement,
-            "SELECT x, y, strftime('%s', created_at) as created_at FROM pixel WHERE created_at > '2025-02-28 17:00:00'"
-        )
-        .fetch_all(&pool)
-        .await
-        .unwrap();
-
-    let (child, mut stdin) = start_ffmpeg(fps, "agemap").expect("failed to start ffmpeg");
-
-    render_timelapse(
-        &placements,
-        &mut stdin,
-        pixels_per_frame,
-        min_seconds_per_frame,
-    )
-    .await;
-
-    drop(stdin);
-    child.wait_with_output().expect("Failed to wait on child");
-
-    println!("Done!");
+/// `90` seconds as `1:30 MIN`, and whole minutes without the seconds.
+fn minutes(seconds: i64) -> String {
+    match seconds % 60 {
+        0 => format!("{} MIN", seconds / 60),
+        rest => format!("{}:{rest:02} MIN", seconds / 60),
+    }
 }
 
-async fn render_timelapse(
-    placements: &[Placement],
-    stdin: &mut ChildStdin,
-    pixels_per_frame: i32,
-    min_seconds_per_frame: i32,
-) {
-    let mut canvas_size_idx = 0;
-    let mut placement_offset = pixel_offset(canvas_size_idx);
-    let mut frame_start_time = 0;
-    let mut remaining_pixels: i32 = 0;
-    let pixel_lifetime: i32 = 60;
-    const FINAL_CANVAS_SIZE: (u32, u32) = CANVAS_SIZES[CANVAS_SIZES.len() - 1];
-    let mut pixel_age = vec![vec![0; FINAL_CANVAS_SIZE.1 as usize]; FINAL_CANVAS_SIZE.0 as usize];
-
-    for pixel in placements {
-        let x = pixel.x as u32;
-        let y = pixel.y as u32;
-        let timestamp: i32 = pixel.created_at.as_ref().unwrap().parse().unwrap();
-
-        if remaining_pixels <= 0 && timestamp - frame_start_time >= min_seconds_per_frame {
-            pixel_age.iter_mut().for_each(|row| {
-                row.iter_mut().for_each(|age| {
-                    if *age > 0 {
-                        *age -= 1;
-                    }
-                })
-            });
-
-            let mut image = blank_image_borders(canvas_size_idx, true);
-            let (width, height) = CANVAS_SIZES[canvas_size_idx];
-            for (x, col) in pixel_age.iter().enumerate() {
-                if x > width as usize {
-                    continue;
-                }
-                for (y, age) in col.iter().enumerate() {
-                    if y > height as usize {
-                        continue;
-                    }
-                    image.put_pixel(
-                        x as u32 + placement_offset.0,
-                        y as u32 + placement_offset.1,
-                        heatmap_color(*age as f32 / pixel_lifetime as f32),
-                    );
-                }
-            }
+pub struct AgemapPanel {
+    canvas: FrameSize,
+    bounds: Expansion,
+    /// When each pixel was placed last, row by row over all of `bounds`.
+    placed: Vec<i64>,
+    age: AgeModel,
+    legend: bool,
+    /// The last frame, and the expansion it shows. Only the canvas is drawn over, the borders stay.
+    image: RgbaImage,
+    expansion: Option<Expansion>,
+}
 
-            let raw_frame = image.as_raw().clone();
-            stdin.write_all(&raw_frame).expect("Failed to write frame");
-            remaining_pixels = pixels_per_frame;
-            frame_start_time = timestamp;
+impl AgemapPanel {
+    pub fn new(event: &Event, canvas: FrameSize, age: AgeModel, legend: bool) -> Self {
+        let bounds = event.schedule.bounds();
+        AgemapPanel {
+            canvas,
+            bounds,
+            // Never placed is as old as it gets.
+            placed: vec![i64::MIN; (bounds.width * bounds.height) as usize],
+            age,
+            legend,
+            image: RgbaImage::new(canvas.width, canvas.height),
+            expansion: None,
         }
+    }
+}
 
-        remaining_pixels -= 1;
+impl Panel for AgemapPanel {
+    fn placed(&mut self, _replay: &CanvasReplay, placement: &Placement, _previous: &PixelState) {
+        let (grid_x, grid_y) = self.bounds.local(placement.x, placement.y);
+        self.placed[(grid_y * self.bounds.width + grid_x) as usize] = placement.timestamp;
+    }
 
-        if x > CANVAS_SIZES[canvas_size_idx].0 || y > CANVAS_SIZES[canvas_size_idx].1 {
-            canvas_size_idx += 1;
-            placement_offset = pixel_offset(canvas_size_idx);
+    fn frame(&mut self, time: i64, expansion: &Expansion) -> &RgbaImage {
+        if self.expansion.as_ref() != Some(expansion) {
+            self.image = blank_image_borders(&self.canvas, expansion, true);
+            self.expansion = Some(*expansion);
         }
+        render_frame(&mut self.image, &self.canvas, &self.placed, &self.bounds, expansion, &self.age, time);
+        &self.image
+    }
 
-        pixel_age[x as usize][y as usize] = pixel_lifetime;
+    fn overlay(&self, image: &mut RgbaImage, frame: &FrameSize) {
+        if self.legend {
+            let size = frame.text_size();
+            self.age.draw_legend(image, frame.width - frame.sidebar - 2 * size, frame.height - 2 * size, size);
+        }
     }
+}
 
-    if remaining_pixels < pixels_per_frame {
-        pixel_age.iter_mut().for_each(|row| {
-            row.iter_mut().for_each(|age| {
-                if *age > 0 {
-                    *age -= 1;
-                }
-            })
-        });
+pub async fn agemap(
+    source: &impl PlacementSource,
+    event: &Event,
+    pacing: &Pacing,
+    encoding: &Encoding,
+    age: AgeModel,
+    legend: bool,
+) {
+    let panel = AgemapPanel::new(event, encoding.canvas, age, legend);
+    render(source, event, pacing, encoding, "agemap", panel, None).await;
+}
 
-        let mut image = blank_image_borders(canvas_size_idx, true);
-        let (width, height) = CANVAS_SIZES[canvas_size_idx];
-        for (x, col) in pixel_age.iter().enumerate() {
-            if x > width as usize {
-                continue;
-            }
-            for (y, age) in col.iter().enumerate() {
-                if y > height as usize {
-                    continue;
-                }
-                image.put_pixel(
-                    x as u32 + placement_offset.0,
-                    y as u32 + placement_offset.1,
-                    heatmap_color(*age as f32 / pixel_lifetime as f32),
-                );
+fn render_frame(
+    image: &mut RgbaImage,
+    frame: &FrameSize,
+    placed: &[i64],
+    bounds: &Expansion,
+    expansion: &Expansion,
+    age: &AgeModel,
+    time: i64,
+) {
+    let (x_offset, y_offset) = pixel_offset(frame, expansion);
+    let (grid_x, grid_y) = bounds.local(expansion.x, expansion.y);
+    let (x_offset, width) = (x_offset as usize * 4, expansion.width as usize);
+    image
+        .par_chunks_exact_mut(frame.width as usize * 4)
+        .skip(y_offset as usize)
+        .take(expansion.height as usize)
+        .enumerate()
+        .for_each(|(y, line)| {
+            let start = ((grid_y as usize + y) * bounds.width as usize) + grid_x as usize;
+            let placed = &placed[start..start + width];
+            let pixels = line[x_offset..x_offset + width * 4].chunks_exact_mut(4);
+            for (pixel, placed) in pixels.zip(placed) {
+                pixel.copy_from_slice(&age.color(time.saturating_sub(*placed)).0);
             }
-        }
-
-        let raw_frame = image.as_raw().clone();
-        stdin
-            .write_all(&raw_frame)
-            .expect("Failed to write last frame");
-    }
+        });
 }
@@ -0,0 +1,170 @@
+use crate::source::PlacementSource;
+use crate::util::color::{parse_hex, rgba_to_hex};
+use crate::util::expansion::ExpansionSchedule;
+use crate::util::io::print_write;
+use crate::util::time::{format_timestamp, TimeWindow};
+use futures_util::StreamExt;
+use image::Rgba;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::process::exit;
+
+// How many examples to list for each problem.
+const EXAMPLES: usize = 5;
+
+struct Finding {
+    description: &'static str,
+    error: bool,
+    count: u64,
+    examples: Vec<String>,
+}
+
+impl Finding {
+    fn new(description: &'static str, error: bool) -> Self {
+        Finding {
+            description,
+            error,
+            count: 0,
+            examples: vec![],
+        }
+    }
+
+    fn add(&mut self, example: impl FnOnce() -> String) {
+        self.count += 1;
+        if self.examples.len() < EXAMPLES {
+            self.examples.push(example());
+        }
+    }
+}
+
+/// Scans the placements for anything the other modules can't handle or would get wrong,
+/// and exits with an error code if there are errors.
+/// The bounds are only checked against configured expansions, as inferred ones fit every placement.
+pub async fn check(
+    source: &impl PlacementSource,
+    window: &TimeWindow,
+    schedule: Option<&ExpansionSchedule>,
+    palette: &[Rgba<u8>],
+    output: &Path,
+) {
+    let output = File::create(output.join("check.txt")).unwrap();
+    let mut writer = BufWriter::new(output);
+
+    // The placements can't be read with malformed rows or colours, so check those first.
+    let mut unreadable: Vec<Finding> = vec![];
+    let mut rows = source.malformed();
+    while let Some(row) = rows.next().await {
+        let idx = match unreadable.iter().position(|finding| finding.description == row.problem) {
+            Some(idx) => idx,
+            None => {
+                unreadable.push(Finding::new(row.problem, true));
+                unreadable.len() - 1
+            }
+        };
+        unreadable[idx].add(|| row.example);
+    }
+    drop(rows);
+    let mut malformed = Finding::new("malformed colours", true);
+    let mut colors: Vec<(String, u64)> = source.colors().await.into_iter().collect();
+    colors.sort();
+    for (hex, count) in colors {
+        if parse_hex(&hex).is_none() {
+            malformed.add(|| format!("'{hex}', in {count} placements"));
+        }
+    }
+    unreadable.push(malformed);
+    if unreadable.iter().any(|finding| finding.count > 0) {
+        unreadable.retain(|finding| finding.count > 0);
+        report(&mut writer, &unreadable);
+        print_write(&mut writer, "Fix these to check the placements.");
+        writer.flush().unwrap();
+        exit(1);
+    }
+
+    let users = source.users().await;
+    let mut out_of_bounds = Finding::new("placements outside the canvas", true);
+    let mut out_of_order = Finding::new("placements out of order", true);
+    let mut off_palette = Finding::new("placements with a colour outside the palette", false);
+    let mut missing_users = Finding::new("placements by unknown users", false);
+    let mut duplicates = Finding::new("duplicate placements", false);
+
+    let mut placements = source.placements(window);
+    let mut total = 0;
+    let mut previous = i64::MIN;
+    // Duplicates are only looked for within the same second, to not keep every placement around.
+    let mut same_second: HashSet<(i32, i32, [u8; 4], u64, bool)> = HashSet::new();
+    while let Some(pixel) = placements.next().await {
+        let (x, y, timestamp) = (pixel.x, pixel.y, pixel.timestamp);
+        let time = || format_timestamp(timestamp);
+        total += 1;
+
+        let canvas = schedule.map(|schedule| schedule.at(timestamp));
+        if let Some(canvas) = canvas.filter(|canvas| !canvas.contains(x, y)) {
+            out_of_bounds.add(|| {
+                format!(
+                    "({x}, {y}) at {}, the canvas was {}x{} from ({}, {})",
+                    time(),
+                    canvas.width,
+                    canvas.height,
+                    canvas.x,
+                    canvas.y
+                )
+            });
+        }
+        if timestamp < previous {
+            out_of_order.add(|| format!("{} after {}", time(), format_timestamp(previous)));
+        }
+        if !palette.contains(&pixel.color) {
+            off_palette.add(|| format!("{} at ({x}, {y}), {}", rgba_to_hex(pixel.color), time()));
+        }
+        if pixel.user == 0 || !users.contains_key(&pixel.user) {
+            missing_users.add(|| format!("user {} at ({x}, {y}), {}", pixel.user, time()));
+        }
+
+        if timestamp != previous {
+            same_second.clear();
+        }
+        if !same_second.insert((x, y, pixel.color.0, pixel.user, pixel.mod_action)) {
+            duplicates.add(|| format!("({x}, {y}) by user {} at {}", pixel.user, time()));
+        }
+        previous = timestamp;
+    }
+
+    print_write(&mut writer, format!("Checked {total} placements").as_str());
+    let mut findings = vec![out_of_order, off_palette, missing_users, duplicates];
+    match schedule {
+        Some(_) => findings.insert(0, out_of_bounds),
+        None => print_write(&mut writer, "No expansions configured, skipped checking the canvas bounds"),
+    }
+    report(&mut writer, &findings);
+    writer.flush().unwrap();
+
+    if findings.iter().any(|finding| finding.error && finding.count > 0) {
+        exit(1);
+    }
+}
+
+fn report(writer: &mut BufWriter<File>, findings: &[Finding]) {
+    for finding in findings {
+        let level = match (finding.count, finding.error) {
+            (0, _) => "ok",
+            (_, true) => "error",
+            (_, false) => "warning",
+        };
+        print_write(
+            writer,
+            format!("{level}: {} {}", finding.count, finding.description).as_str(),
+        );
+        for example in &finding.examples {
+            print_write(writer, format!("    {example}").as_str());
+        }
+        if finding.count > finding.examples.len() as u64 {
+            print_write(
+                writer,
+                format!("    and {} more", finding.count - finding.examples.len() as u64).as_str(),
+            );
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use crate::config::Event;
+use crate::modules::agemap::{AgeModel, AgemapPanel};
+use crate::modules::heatmap::heatmap_color;
+use crate::modules::timelapse::TimelapsePanel;
+use crate::modules::virginmap::VirginmapPanel;
+use crate::source::{Placement, PlacementSource};
+use crate::util::color::UserColors;
+use crate::util::encoder::Encoding;
+use crate::util::expansion::Expansion;
+use crate::util::font::{draw_text, fill, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::util::render::{
+    blank_image_borders_with_colour, extend_canvas_with_colour, frame_position, FrameSize, Pacing,
+    BLACK,
+};
+use crate::util::replay::{CanvasReplay, PixelState};
+use crate::util::video::{render, Panel};
+use clap::ValueEnum;
+use image::{Rgba, RgbaImage};
+use serde::Deserialize;
+
+const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const GREY: Rgba<u8> = Rgba([40, 40, 40, 255]);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum CompositeView {
+    /// The canvas, like `timelapse`.
+    Normal,
+    /// The pixels nobody placed on yet, like `virginmap`.
+    Virgin,
+    /// How recently each pixel changed, like `agemap`.
+    Age,
+    /// How often each pixel changed so far, like `heatmap`.
+    Heat,
+    /// Who owns each pixel, like `usermap`.
+    Usermap,
+}
+
+impl CompositeView {
+    fn label(&self) -> &'static str {
+        match self {
+            CompositeView::Normal => "CANVAS",
+            CompositeView::Virgin => "VIRGIN",
+            CompositeView::Age => "AGE",
+            CompositeView::Heat => "HEAT",
+            CompositeView::Usermap => "USERS",
+        }
+    }
+}
+
+/// How often each pixel changed, with `max_heat` changes or more being the hottest.
+struct HeatPanel {
+    image: RgbaImage,
+    canvas: FrameSize,
+    max_heat: i32,
+}
+
+impl Panel for HeatPanel {
+    fn expanded(&mut self, replay: &CanvasReplay, previous: &Expansion) {
+        self.image = extend_canvas_with_colour(&self.canvas, &self.image, previous, replay.expansion(), BLACK, GREY);
+    }
+
+    fn placed(&mut self, replay: &CanvasReplay, placement: &Placement, _previous: &PixelState) {
+        let changes = replay.pixel(placement.x, placement.y).changes;
+        let heat = heatmap_color((changes as f32 / self.max_heat as f32).min(1.0));
+        let (frame_x, frame_y) = frame_position(&self.canvas, replay.expansion(), placement.x, placement.y);
+        self.image.put_pixel(frame_x, frame_y, Rgba([heat[0], heat[1], heat[2], 255]));
+    }
+
+    fn frame(&mut self, _time: i64, _expansion: &Expansion) -> &RgbaImage {
+        &self.image
+    }
+}
+
+/// Each pixel in the colour of its owner.
+struct UsermapPanel {
+    image: RgbaImage,
+    canvas: FrameSize,
+    colors: UserColors,
+}
+
+impl Panel for UsermapPanel {
+    fn expanded(&mut self, replay: &CanvasReplay, previous: &Expansion) {
+        self.image = extend_canvas_with_colour(&self.canvas, &self.image, previous, replay.expansion(), WHITE, GREY);
+    }
+
+    fn placed(&mut self, replay: &CanvasReplay, placement: &Placement, _previous: &PixelState) {
+        let color = match replay.pixel(placement.x, placement.y).owner {
+            0 => WHITE,
+            owner => self.colors.get(owner),
+        };
+        let (frame_x, frame_y) = frame_position(&self.canvas, replay.expansion(), placement.x, placement.y);
+        self.image.put_pixel(frame_x, frame_y, color);
+    }
+
+    fn frame(&mut self, _time: i64, _expansion: &Expansion) -> &RgbaImage {
+        &self.image
+    }
+}
+
+/// The views side by side, in rows of `columns`, each with its name in the corner.
+struct CompositePanel {
+    panels: Vec<(CompositeView, Box<dyn Panel>)>,
+    tile: FrameSize,
+    columns: u32,
+    /// The top left corner of the tiles, which are centred in the frame.
+    offset: (u32, u32),
+    text_size: u32,
+    image: RgbaImage,
+    /// Drawn over the age views.
+    legend: Option<AgeModel>,
+}
+
+impl Panel for CompositePanel {
+    fn expanded(&mut self, replay: &CanvasReplay, previous: &Expansion) {
+        for (_, panel) in &mut self.panels {
+            panel.expanded(replay, previous);
+        }
+    }
+
+    fn placed(&mut self, replay: &CanvasReplay, placement: &Placement, previous: &PixelState) {
+        for (_, panel) in &mut self.panels {
+            panel.placed(replay, placement, previous);
+        }
+    }
+
+    fn frame(&mut self, time: i64, expansion: &Expansion) -> &RgbaImage {
+        let size = self.text_size;
+        for (i, (view, panel)) in self.panels.iter_mut().enumerate() {
+            let left = self.offset.0 + i as u32 % self.columns * self.tile.width;
+            let top = self.offset.1 + i as u32 / self.columns * self.tile.height;
+            let tile = panel.frame(time, expansion);
+            let (row, width) = (self.image.width() as usize * 4, tile.width() as usize * 4);
+            for (y, line) in tile.chunks_exact(width).enumerate() {
+                let start = (top as usize + y) * row + left as usize * 4;
+                (*self.image)[start..start + width].copy_from_slice(line);
+            }
+
+            let label = view.label();
+            let label_top = top + self.tile.height - (GLYPH_HEIGHT + 4) * size;
+            let label_width = (label.len() as u32 * (GLYPH_WIDTH + 1) + 3) * size;
+            fill(&mut self.image, left, label_top, label_width, (GLYPH_HEIGHT + 4) * size, BLACK);
+            draw_text(&mut self.image, label, left + 2 * size, label_top + 2 * size, size, WHITE);
+        }
+        &self.image
+    }
+
+    fn overlay(&self, image: &mut RgbaImage, _frame: &FrameSize) {
+        let Some(age) = &self.legend else {
+            return;
+        };
+        let size = self.text_size;
+        for (i, (view, _)) in self.panels.iter().enumerate() {
+            if *view == CompositeView::Age {
+                let right = self.offset.0 + (i as u32 % self.columns + 1) * self.tile.width;
+                let bottom = self.offset.1 + (i as u32 / self.columns + 1) * self.tile.height;
+                age.draw_legend(image, right - 2 * size, bottom - 2 * size, size);
+            }
+        }
+    }
+}
+
+/// How many columns the views are laid out in, as square as it gets unless `columns` is set.
+pub fn columns(views: usize, columns: Option<u32>) -> u32 {
+    columns.unwrap_or((views as f64).sqrt().ceil() as u32).clamp(1, views.max(1) as u32)
+}
+
+pub struct Layout {
+    pub views: Vec<CompositeView>,
+    pub columns: u32,
+    /// The frame each view is drawn on, `encoding.canvas` fits the rows of them.
+    pub tile: FrameSize,
+    /// Whether the age views show what their colours mean.
+    pub legend: bool,
+}
+
+pub async fn composite(
+    source: &impl PlacementSource,
+    event: &Event,
+    pacing: &Pacing,
+    encoding: &Encoding,
+    layout: &Layout,
+    age: AgeModel,
+    max_heat: i32,
+) {
+    let Layout { views, columns, tile, legend } = layout;
+    let (columns, tile) = (*columns, *tile);
+    let panels = views
+        .iter()
+        .map(|view| {
+            let panel: Box<dyn Panel> = match view {
+                CompositeView::Normal => Box::new(TimelapsePanel::new(event, tile, None)),
+                CompositeView::Virgin => Box::new(VirginmapPanel::new(event, tile)),
+                CompositeView::Age => Box::new(AgemapPanel::new(event, tile, age, false)),
+                CompositeView::Heat => Box::new(HeatPanel {
+                    image: blank_image_borders_with_colour(&tile, event.schedule.get(0), BLACK, GREY),
+                    canvas: tile,
+                    max_heat,
+                }),
+                CompositeView::Usermap => Box::new(UsermapPanel {
+                    image: blank_image_borders_with_colour(&tile, event.schedule.get(0), WHITE, GREY),
+                    canvas: tile,
+                    colors: UserColors::load(),
+                }),
+            };
+            (*view, panel)
+        })
+        .collect::<Vec<_>>();
+
+    let canvas = encoding.canvas;
+    let rows = (views.len() as u32).div_ceil(columns);
+    let panel = CompositePanel {
+        panels,
+        tile,
+        columns,
+        offset: (
+            (canvas.width - canvas.sidebar - columns * tile.width) / 2,
+            (canvas.height - rows * tile.height) / 2,
+        ),
+        text_size: encoding.frame.text_size(),
+        image: RgbaImage::from_pixel(canvas.width, canvas.height, BLACK),
+        legend: legend.then_some(age),
+    };
+    render(source, event, pacing, encoding, "composite", panel, None).await;
+}
@@ -0,0 +1,318 @@
+use crate::util::expansion::{Expansion, ExpansionEntry, ExpansionSchedule};
+use crate::util::time::format_timestamp;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::{ConnectOptions, Connection, SqliteConnection};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::path::Path;
+
+// Canvas sizes used when the event config has `expansions = []`, spread evenly over the event.
+const DEFAULT_SIZES: [(u32, u32); 3] = [(500, 281), (500, 540), (960, 540)];
+const MOD_USER_ID: i64 = 1;
+const WIPE_COLOR: &str = "#FFFFFF";
+
+const ADJECTIVES: [&str; 8] = ["Silent", "Happy", "Tiny", "Brave", "Sleepy", "Clever", "Lucky", "Fuzzy"];
+const NOUNS: [&str; 8] = ["Otter", "Circle", "Pixel", "Slider", "Spinner", "Cookie", "Mapper", "Fox"];
+
+const SCHEMA: [&str; 4] = [
+    "CREATE TABLE user (user_id INTEGER PRIMARY KEY, is_banned INTEGER NOT NULL DEFAULT 0)",
+    "CREATE TABLE discord_user (
+        user_id INTEGER PRIMARY KEY REFERENCES user (user_id),
+        discord_id TEXT NOT NULL UNIQUE,
+        discord_username TEXT NOT NULL
+    )",
+    "CREATE TABLE pixel (
+        x INTEGER NOT NULL,
+        y INTEGER NOT NULL,
+        color TEXT NOT NULL,
+        user_id INTEGER NOT NULL REFERENCES user (user_id),
+        created_at TEXT NOT NULL,
+        mod_action INTEGER NOT NULL DEFAULT 0
+    )",
+    "CREATE INDEX pixel_created_at ON pixel (created_at)",
+];
+
+pub struct Settings {
+    pub users: u32,
+    pub duration: i64,
+    pub cooldown: i64,
+    pub artworks: u32,
+    pub banned: u32,
+    pub mod_actions: u32,
+    pub seed: u64,
+}
+
+struct SimUser {
+    banned: bool,
+    artwork: usize,
+    leave: i64,
+    cursor: u32,
+}
+
+#[derive(Clone, Copy)]
+enum Pattern {
+    Solid,
+    Stripes,
+    Checkerboard,
+    Circle,
+    Frame,
+}
+
+/// A drawing a group of users works on together, and keeps repairing.
+struct Artwork {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    pattern: Pattern,
+    colors: (usize, usize),
+}
+
+impl Artwork {
+    fn random(rng: &mut StdRng, canvas: &Expansion, palette_len: usize) -> Self {
+        let width = rng.gen_range(8..=48).min(canvas.width);
+        let height = rng.gen_range(8..=48).min(canvas.height);
+        let first = rng.gen_range(0..palette_len);
+        let second = (first + rng.gen_range(1..palette_len)) % palette_len;
+        let pattern = [
+            Pattern::Solid,
+            Pattern::Stripes,
+            Pattern::Checkerboard,
+            Pattern::Circle,
+            Pattern::Frame,
+        ]
+        .choose(rng)
+        .copied()
+        .unwrap();
+        Artwork {
+            x: canvas.x + rng.gen_range(0..=canvas.width - width) as i32,
+            y: canvas.y + rng.gen_range(0..=canvas.height - height) as i32,
+            width,
+            height,
+            pattern,
+            colors: (first, second),
+        }
+    }
+
+    fn area(&self) -> u32 {
+        self.width * self.height
+    }
+
+    /// The palette index the artwork wants at its `n`th pixel, if any.
+    fn pixel(&self, n: u32) -> Option<(i32, i32, usize)> {
+        let (dx, dy) = (n % self.width, n / self.width);
+        let (a, b) = self.colors;
+        let color = match self.pattern {
+            Pattern::Solid => a,
+            Pattern::Stripes => [a, b][(dy / 2 % 2) as usize],
+            Pattern::Checkerboard => [a, b][((dx / 4 + dy / 4) % 2) as usize],
+            Pattern::Circle => {
+                let radius = self.width.min(self.height) as i32 / 2;
+                let (cx, cy) = (dx as i32 - radius, dy as i32 - radius);
+                if cx * cx + cy * cy > radius * radius {
+                    return None;
+                }
+                a
+            }
+            Pattern::Frame => {
+                if dx == 0 || dy == 0 || dx == self.width - 1 || dy == self.height - 1 {
+                    a
+                } else {
+                    b
+                }
+            }
+        };
+        Some((self.x + dx as i32, self.y + dy as i32, color))
+    }
+}
+
+/// Writes a made up event into a new SQLite database, with the tables and columns the tools read.
+/// Users draw artworks in groups, banned users grief them, and a moderator wipes areas now and then.
+pub async fn generate(
+    destination: &Path,
+    settings: &Settings,
+    start: i64,
+    palette: &[String],
+    entries: &[ExpansionEntry],
+) {
+    assert!(
+        !destination.exists(),
+        "{} already exists, generate into a new file",
+        destination.display()
+    );
+    assert!(palette.len() >= 2, "the palette needs at least two colours");
+    assert!(settings.duration >= 60, "the event needs to last at least a minute");
+    let end = start + settings.duration;
+    let schedule = if entries.is_empty() {
+        ExpansionSchedule::new(
+            DEFAULT_SIZES
+                .iter()
+                .enumerate()
+                .map(|(idx, (width, height))| Expansion {
+                    x: 0,
+                    y: 0,
+                    width: *width,
+                    height: *height,
+                    at: start + settings.duration * idx as i64 / DEFAULT_SIZES.len() as i64,
+                })
+                .collect(),
+        )
+    } else {
+        ExpansionSchedule::configured(entries)
+    };
+    let mut rng = StdRng::seed_from_u64(settings.seed);
+    let mut artworks: Vec<Option<Artwork>> = (0..settings.artworks.max(1)).map(|_| None).collect();
+
+    let mut conn = SqliteConnectOptions::new()
+        .filename(destination)
+        .create_if_missing(true)
+        .connect()
+        .await
+        .unwrap();
+    let mut tx = conn.begin().await.unwrap();
+    for statement in SCHEMA {
+        sqlx::query(statement).execute(&mut *tx).await.unwrap();
+    }
+
+    let mut banned: Vec<bool> = (0..settings.users)
+        .map(|idx| idx < settings.banned)
+        .collect();
+    banned.shuffle(&mut rng);
+    let mut users = vec![];
+    let mut turns = BinaryHeap::new();
+    insert_user(&mut tx, MOD_USER_ID, "Moderator", false).await;
+    for (idx, banned) in banned.into_iter().enumerate() {
+        let name = format!(
+            "{}{}{idx}",
+            ADJECTIVES.choose(&mut rng).unwrap(),
+            NOUNS.choose(&mut rng).unwrap()
+        );
+        insert_user(&mut tx, user_id(idx), &name, banned).await;
+
+        let join = start + rng.gen_range(0..settings.duration * 3 / 4);
+        let leave = (join + rng.gen_range(settings.duration / 20..=settings.duration / 2)).min(end);
+        turns.push(Reverse((join, idx)));
+        users.push(SimUser {
+            banned,
+            artwork: rng.gen_range(0..artworks.len()),
+            leave,
+            cursor: rng.gen(),
+        });
+    }
+
+    let mut wipes: Vec<i64> = (0..settings.mod_actions)
+        .map(|_| start + rng.gen_range(0..settings.duration))
+        .collect();
+    wipes.sort_unstable_by_key(|time| Reverse(*time));
+    let wipe_color = palette.iter().position(|color| color == WIPE_COLOR);
+
+    let mut canvas: HashMap<(i32, i32), usize> = HashMap::new();
+    let mut placements = 0;
+    while let Some(Reverse((timestamp, idx))) = turns.pop() {
+        while wipes.last().is_some_and(|wipe| *wipe <= timestamp) {
+            let wipe = wipes.pop().unwrap();
+            let area = schedule.at(wipe);
+            let size = rng.gen_range(5..=20).min(area.width).min(area.height);
+            let left = area.x + rng.gen_range(0..=area.width - size) as i32;
+            let top = area.y + rng.gen_range(0..=area.height - size) as i32;
+            for x in left..left + size as i32 {
+                for y in top..top + size as i32 {
+                    match wipe_color {
+                        Some(color) => canvas.insert((x, y), color),
+                        None => canvas.remove(&(x, y)),
+                    };
+                    insert_pixel(&mut tx, x, y, WIPE_COLOR, MOD_USER_ID, wipe, true).await;
+                    placements += 1;
+                }
+            }
+        }
+
+        let area = schedule.at(timestamp);
+        let user = &mut users[idx];
+        let placement = if user.banned {
+            Some((
+                area.x + rng.gen_range(0..area.width) as i32,
+                area.y + rng.gen_range(0..area.height) as i32,
+                rng.gen_range(0..palette.len()),
+            ))
+        } else {
+            let artwork = artworks[user.artwork]
+                .get_or_insert_with(|| Artwork::random(&mut rng, area, palette.len()));
+            // Work on the first pixel that isn't right yet, starting where we left off.
+            let cursor = user.cursor;
+            let next = (0..artwork.area())
+                .map(|n| cursor.wrapping_add(n) % artwork.area())
+                .filter_map(|n| artwork.pixel(n).map(|pixel| (n, pixel)))
+                .find(|(_, (x, y, color))| canvas.get(&(*x, *y)) != Some(color));
+            next.map(|(n, pixel)| {
+                user.cursor = n;
+                pixel
+            })
+        };
+        if let Some((x, y, color)) = placement {
+            canvas.insert((x, y), color);
+            insert_pixel(&mut tx, x, y, &palette[color], user_id(idx), timestamp, false).await;
+            placements += 1;
+        }
+
+        let next = timestamp + settings.cooldown + rng.gen_range(0..=settings.cooldown / 2);
+        if next < user.leave {
+            turns.push(Reverse((next, idx)));
+        }
+    }
+    tx.commit().await.unwrap();
+    conn.close().await.unwrap();
+
+    println!(
+        "Generated {placements} placements by {} users into {}",
+        settings.users,
+        destination.display()
+    );
+}
+
+// The moderator is user 1, everyone else follows.
+fn user_id(idx: usize) -> i64 {
+    MOD_USER_ID + 1 + idx as i64
+}
+
+async fn insert_user(conn: &mut SqliteConnection, user_id: i64, name: &str, banned: bool) {
+    sqlx::query("INSERT INTO user (user_id, is_banned) VALUES (?, ?)")
+        .bind(user_id)
+        .bind(banned as i64)
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+    sqlx::query("INSERT INTO discord_user (user_id, discord_id, discord_username) VALUES (?, ?, ?)")
+        .bind(user_id)
+        .bind((100_000_000_000_000_000 + user_id).to_string())
+        .bind(name)
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+}
+
+async fn insert_pixel(
+    conn: &mut SqliteConnection,
+    x: i32,
+    y: i32,
+    color: &str,
+    user_id: i64,
+    timestamp: i64,
+    mod_action: bool,
+) {
+    sqlx::query(
+        "INSERT INTO pixel (x, y, color, user_id, created_at, mod_action) VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(x)
+    .bind(y)
+    .bind(color)
+    .bind(user_id)
+    .bind(format_timestamp(timestamp))
+    .bind(mod_action as i64)
+    .execute(conn)
+    .await
+    .unwrap();
+}
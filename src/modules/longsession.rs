@@ -1,41 +1,33 @@
-use crate::util::db::get_user_map;
+use crate::config::Event;
+use crate::source::{PlacementSource, User};
 use crate::util::io::print_write;
-use sqlx::{query, Pool, Sqlite};
+use futures_util::StreamExt;
+use std::cmp::Reverse;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::BufWriter;
 use std::io::Write;
 
-const MIN_PIXELS: i32 = 50;
+pub async fn longsession(source: &impl PlacementSource, event: &Event, seconds: i64, min_pixels: i32) {
+    let users: HashMap<u64, User> = source.users().await;
+    let mut placements = source.placements(&event.window);
+    let mut active_sessions: HashMap<u64, (i64, i64, i32)> = HashMap::new();
+    let mut sessions: Vec<(u64, i64, i32)> = vec![];
 
-pub async fn longsession(pool: Pool<Sqlite>, seconds: &i32) {
-    let placements = query!(
-        "SELECT du.discord_id as user_id, strftime('%s', p.created_at) as created_at, mod_action
-        FROM pixel as p
-        JOIN discord_user du ON p.user_id = du.user_id
-        WHERE p.created_at > '2025-02-28 17:00:00'"
-    )
-    .fetch_all(&pool)
-    .await
-    .unwrap();
-    let users: HashMap<u64, String> = get_user_map(pool).await;
-    let mut active_sessions: HashMap<u64, (i32, i32, i32)> = HashMap::new();
-    let mut sessions: Vec<(u64, i32, i32)> = vec![];
-
-    for pixel in placements {
-        if pixel.mod_action == 1 {
+    while let Some(pixel) = placements.next().await {
+        if pixel.mod_action || !users.contains_key(&pixel.user) {
             continue;
         }
-        let timestamp: i32 = pixel.created_at.clone().unwrap().parse().unwrap();
-        let user_id: u64 = pixel.user_id.parse().unwrap();
+        let timestamp = pixel.timestamp;
+        let user_id = pixel.user;
         let current_session = active_sessions
             .entry(user_id)
             .or_insert_with(|| (timestamp, timestamp, 0));
 
         let (session_start, session_last, pixels) = current_session;
         let pixel_duration = timestamp - *session_last;
-        if pixel_duration > *seconds {
-            if *pixels > MIN_PIXELS {
+        if pixel_duration > seconds {
+            if *pixels > min_pixels {
                 sessions.push((user_id, *session_start, *pixels));
             }
             *current_session = (timestamp, timestamp, 1);
@@ -47,13 +39,13 @@ pub async fn longsession(pool: Pool<Sqlite>, seconds: &i32) {
     // Store remaining sessions
     for (user, session) in active_sessions.iter() {
         let (session_start, _, pixels) = *session;
-        if pixels > MIN_PIXELS {
+        if pixels > min_pixels {
             sessions.push((*user, session_start, pixels));
         }
     }
 
-    sessions.sort_by(|a, b| b.2.cmp(&a.2));
-    let output = File::create("output/longsession.txt").unwrap();
+    sessions.sort_by_key(|session| Reverse(session.2));
+    let output = File::create(event.output_path("longsession.txt")).unwrap();
     let mut writer = BufWriter::new(output);
     print_write(
         &mut writer,
@@ -61,7 +53,7 @@ pub async fn longsession(pool: Pool<Sqlite>, seconds: &i32) {
     );
     for (i, session) in sessions.iter().enumerate() {
         let (user_id, session_start, pixels) = session;
-        let username = users.get(user_id).unwrap();
+        let username = &users[user_id].name;
         let index = i + 1;
         print_write(
             &mut writer,
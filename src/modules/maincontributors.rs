@@ -1,31 +1,37 @@
+use crate::config::Event;
+use crate::source::{PlacementSource, User};
 use crate::util::io::print_write;
-use sqlx::{query, query_scalar, Pool, Sqlite};
+use futures_util::StreamExt;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::BufWriter;
 use std::io::Write;
 
-pub async fn maincontributors(pool: Pool<Sqlite>, percentage: &i32) {
-    let user_counts = query!(
-        "SELECT COUNT(*) as count, du.discord_username as username
-        FROM pixel as p
-        JOIN discord_user du ON p.user_id = du.user_id
-        WHERE p.mod_action = 0 AND p.created_at > '2025-02-28 17:00:00'
-        GROUP BY du.user_id
-        ORDER BY COUNT(*) DESC"
-    )
-    .fetch_all(&pool)
-    .await
-    .unwrap();
-    let pixel_count = query_scalar!("SELECT COUNT(*) FROM pixel WHERE mod_action = 0 AND created_at > '2025-02-28 17:00:00'").fetch_one(&pool).await.unwrap();
-    let mut pixel_cap = (pixel_count as f32 * (*percentage as f32 / 100.0)) as i64;
+pub async fn maincontributors(source: &impl PlacementSource, event: &Event, percentage: i32) {
+    let users: HashMap<u64, User> = source.users().await;
+    let mut placements = source.placements(&event.window);
 
-    let output = File::create("output/maincontributors.txt").unwrap();
+    let mut pixel_count: i64 = 0;
+    let mut counts: HashMap<u64, i64> = HashMap::new();
+    while let Some(pixel) = placements.next().await {
+        if pixel.mod_action {
+            continue;
+        }
+        pixel_count += 1;
+        if users.contains_key(&pixel.user) {
+            *counts.entry(pixel.user).or_insert(0) += 1;
+        }
+    }
+    let mut user_counts = counts.into_iter().collect::<Vec<(u64, i64)>>();
+    user_counts.sort_by(|(_, a), (_, b)| b.cmp(a));
+    let mut pixel_cap = (pixel_count as f32 * (percentage as f32 / 100.0)) as i64;
+
+    let output = File::create(event.output_path("maincontributors.txt")).unwrap();
     let mut writer = BufWriter::new(output);
     print_write(&mut writer, format!("Users who contributed to {percentage}% of the pixels:").as_str());
-    for (i, user) in user_counts.iter().enumerate() {
-        let pixels = &user.count;
-        let username = &user.username;
-        pixel_cap -= user.count;
+    for (i, (user_id, pixels)) in user_counts.iter().enumerate() {
+        let username = &users[user_id].name;
+        pixel_cap -= pixels;
         if pixel_cap <= 0 {
             break;
         }
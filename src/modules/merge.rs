@@ -0,0 +1,269 @@
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::{ConnectOptions, Connection, SqliteConnection};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// A `discord_user` row joined with its `user`.
+struct MergeUser {
+    user_id: i64,
+    discord_id: String,
+    username: String,
+    banned: bool,
+}
+
+/// Combines several databases of the same event into `destination`, which starts as a copy
+/// of the first one. Users are matched by `discord_id`, placements that are already there
+/// are skipped, and the placements are renumbered in time order at the end.
+pub async fn merge(destination: &Path, databases: &[PathBuf]) {
+    assert!(
+        !destination.exists(),
+        "{} already exists, merge into a new file",
+        destination.display()
+    );
+    // Through SQLite rather than a file copy, so what is still in the first database's -wal comes along.
+    let mut first = SqliteConnectOptions::new()
+        .filename(&databases[0])
+        .read_only(true)
+        .connect()
+        .await
+        .unwrap_or_else(|e| panic!("Failed to open {}: {e}", databases[0].display()));
+    sqlx::query("VACUUM INTO ?")
+        .bind(destination.to_str().expect("Destination path must be valid UTF-8"))
+        .execute(&mut first)
+        .await
+        .unwrap_or_else(|e| panic!("Failed to copy {}: {e}", databases[0].display()));
+    first.close().await.unwrap();
+
+    // ATTACH only applies to one connection, so don't use a pool.
+    let mut conn = SqliteConnectOptions::new()
+        .filename(destination)
+        .connect()
+        .await
+        .unwrap();
+    let mut conflicts: Vec<String> = vec![];
+    // Without it, looking for duplicates scans the whole pixel table for every placement.
+    sqlx::query("CREATE INDEX merge_pixel ON pixel (created_at, x, y)")
+        .execute(&mut conn)
+        .await
+        .unwrap();
+
+    for database in &databases[1..] {
+        println!("Merging {}", database.display());
+        sqlx::query("ATTACH DATABASE ? AS other")
+            .bind(database.to_str().unwrap())
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        let mut tx = conn.begin().await.unwrap();
+
+        let ours: HashMap<String, MergeUser> = users(&mut tx, "main")
+            .await
+            .into_iter()
+            .map(|user| (user.discord_id.clone(), user))
+            .collect();
+        let user_columns = copied_columns(&mut tx, "user").await;
+        let discord_user_columns = copied_columns(&mut tx, "discord_user").await;
+        let mut next_user_id: i64 = sqlx::query_scalar(
+            "SELECT COALESCE(MAX(user_id), 0) + 1 FROM (SELECT user_id FROM user UNION ALL SELECT user_id FROM discord_user)",
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .unwrap();
+
+        sqlx::query("CREATE TEMP TABLE merge_users (old_id INTEGER PRIMARY KEY, new_id INTEGER)")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        let (mut matched, mut added) = (0, 0);
+        for theirs in users(&mut tx, "other").await {
+            let new_id = match ours.get(&theirs.discord_id) {
+                Some(ours) => {
+                    if ours.username != theirs.username {
+                        conflicts.push(format!(
+                            "{}: user {} is called '{}', kept '{}'",
+                            database.display(),
+                            theirs.discord_id,
+                            theirs.username,
+                            ours.username
+                        ));
+                    }
+                    if ours.banned != theirs.banned {
+                        conflicts.push(format!(
+                            "{}: user {} ({}) is only banned in one database, banned them",
+                            database.display(),
+                            theirs.discord_id,
+                            ours.username
+                        ));
+                        sqlx::query("UPDATE user SET is_banned = 1 WHERE user_id = ?")
+                            .bind(ours.user_id)
+                            .execute(&mut *tx)
+                            .await
+                            .unwrap();
+                    }
+                    matched += 1;
+                    ours.user_id
+                }
+                None => {
+                    let new_id = next_user_id;
+                    next_user_id += 1;
+                    copy_row(&mut tx, "user", &user_columns, theirs.user_id, new_id).await;
+                    copy_row(&mut tx, "discord_user", &discord_user_columns, theirs.user_id, new_id).await;
+                    added += 1;
+                    new_id
+                }
+            };
+            sqlx::query("INSERT INTO merge_users (old_id, new_id) VALUES (?, ?)")
+                .bind(theirs.user_id)
+                .bind(new_id)
+                .execute(&mut *tx)
+                .await
+                .unwrap();
+        }
+
+        let pixel_columns = copied_columns(&mut tx, "pixel").await;
+        let select: Vec<String> = pixel_columns
+            .iter()
+            .map(|column| match column.as_str() {
+                "\"user_id\"" => "m.new_id".to_string(),
+                column => format!("p.{column}"),
+            })
+            .collect();
+        let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM other.pixel")
+            .fetch_one(&mut *tx)
+            .await
+            .unwrap();
+        let inserted = sqlx::query(&format!(
+            "INSERT INTO main.pixel ({})
+            SELECT {}
+            FROM other.pixel p
+            JOIN merge_users m ON p.user_id = m.old_id
+            WHERE NOT EXISTS (
+                SELECT 1 FROM main.pixel q
+                WHERE q.created_at = p.created_at AND q.x = p.x AND q.y = p.y
+                    AND q.color = p.color AND q.user_id = m.new_id AND q.mod_action = p.mod_action
+            )
+            ORDER BY p.rowid",
+            pixel_columns.join(", "),
+            select.join(", ")
+        ))
+        .execute(&mut *tx)
+        .await
+        .unwrap()
+        .rows_affected() as i64;
+        let orphans: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM other.pixel p WHERE p.user_id NOT IN (SELECT old_id FROM merge_users)",
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .unwrap();
+        if orphans > 0 {
+            conflicts.push(format!(
+                "{}: skipped {orphans} placements by users missing from discord_user",
+                database.display()
+            ));
+        }
+
+        sqlx::query("DROP TABLE merge_users")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        sqlx::query("DETACH DATABASE other")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        println!(
+            "{matched} users matched, {added} users added, {inserted} placements added, {} duplicates skipped",
+            total - inserted - orphans
+        );
+    }
+
+    sqlx::query("DROP INDEX merge_pixel")
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    println!("Sorting placements");
+    sort_pixels(&mut conn).await;
+    conn.close().await.unwrap();
+
+    if conflicts.is_empty() {
+        println!("Merged without conflicts into {}", destination.display());
+    } else {
+        println!("Merged into {}, with {} conflicts:", destination.display(), conflicts.len());
+        for conflict in &conflicts {
+            println!("{conflict}");
+        }
+    }
+}
+
+async fn users(conn: &mut SqliteConnection, schema: &str) -> Vec<MergeUser> {
+    sqlx::query_as::<_, (i64, String, String, Option<i64>)>(&format!(
+        "SELECT du.user_id, du.discord_id, du.discord_username, u.is_banned
+        FROM {schema}.discord_user du
+        LEFT JOIN {schema}.user u ON du.user_id = u.user_id"
+    ))
+    .fetch_all(conn)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|(user_id, discord_id, username, is_banned)| MergeUser {
+        user_id,
+        discord_id,
+        username,
+        banned: is_banned.unwrap_or(0) != 0,
+    })
+    .collect()
+}
+
+/// The quoted columns of a table, leaving out an integer primary key so SQLite picks a new one.
+/// `user_id` is always kept, since it's renumbered by the merge.
+async fn copied_columns(conn: &mut SqliteConnection, table: &str) -> Vec<String> {
+    sqlx::query_as::<_, (String, String, i64)>("SELECT name, type, pk FROM pragma_table_info(?, 'main')")
+        .bind(table)
+        .fetch_all(conn)
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|(name, kind, pk)| name == "user_id" || *pk == 0 || !kind.eq_ignore_ascii_case("INTEGER"))
+        .map(|(name, _, _)| format!("\"{name}\""))
+        .collect()
+}
+
+async fn copy_row(
+    conn: &mut SqliteConnection,
+    table: &str,
+    columns: &[String],
+    old_id: i64,
+    new_id: i64,
+) {
+    let select: Vec<&str> = columns
+        .iter()
+        .map(|column| if column == "\"user_id\"" { "?" } else { column.as_str() })
+        .collect();
+    sqlx::query(&format!(
+        "INSERT INTO main.{table} ({}) SELECT {} FROM other.{table} WHERE user_id = ?",
+        columns.join(", "),
+        select.join(", ")
+    ))
+    .bind(new_id)
+    .bind(old_id)
+    .execute(conn)
+    .await
+    .unwrap();
+}
+
+/// Rewrites the pixel table in time order, since the sources read it in rowid order.
+async fn sort_pixels(conn: &mut SqliteConnection) {
+    let columns = copied_columns(conn, "pixel").await.join(", ");
+    let mut tx = conn.begin().await.unwrap();
+    for statement in [
+        format!("CREATE TEMP TABLE sorted_pixel AS SELECT {columns} FROM main.pixel ORDER BY created_at, rowid"),
+        "DELETE FROM main.pixel".to_string(),
+        format!("INSERT INTO main.pixel ({columns}) SELECT {columns} FROM sorted_pixel ORDER BY rowid"),
+        "DROP TABLE sorted_pixel".to_string(),
+    ] {
+        sqlx::query(&statement).execute(&mut *tx).await.unwrap();
+    }
+    tx.commit().await.unwrap();
+}
@@ -1,10 +1,15 @@
+pub mod check;
 pub mod currentpixels;
+pub mod generate;
 pub mod heatmap;
 pub mod longsession;
+pub mod merge;
 pub mod singleplace;
 pub mod singleplayer;
+pub mod snapshot;
 pub mod timelapse;
 pub mod usermap;
 pub mod virginmap;
 pub mod maincontributors;
 pub mod agemap;
+pub mod composite;
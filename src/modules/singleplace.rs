@@ -1,33 +1,37 @@
-use sqlx::{query, Pool, Sqlite};
-use crate::config::CANVAS_SIZES;
-use crate::util;
+use crate::source::{Placement, PlacementSource};
 use crate::util::canvas::white_image;
+use crate::util::replay::{CanvasReplay, PixelState, ReplayView};
+use crate::config::Event;
+use image::{ImageBuffer, Rgba};
 
-pub async fn singleplace(pool: Pool<Sqlite>) {
-    let placements = query!("SELECT x, y, color, mod_action FROM pixel WHERE created_at > '2025-02-28 17:00:00'")
-        .fetch_all(&pool)
-        .await
-        .unwrap();
+const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
 
-    const FINAL_CANVAS_SIZE: (u32, u32) = CANVAS_SIZES[CANVAS_SIZES.len() - 1];
-    let mut image = white_image();
-    let mut placed = vec![vec![false; FINAL_CANVAS_SIZE.1 as usize]; FINAL_CANVAS_SIZE.0 as usize];
+struct SingleplaceView {
+    image: ImageBuffer<Rgba<u8>, Vec<u8>>,
+}
 
-    for pixel in placements {
-        let x = pixel.x as u32;
-        let y = pixel.y as u32;
-        if pixel.mod_action == 1 {
-            placed[x as usize][y as usize] = false;
-            continue;
-        }
-        if placed[x as usize][y as usize] {
-            continue;
-        }
-        
-        let color = util::color::hex_to_rgba(&pixel.color);
-        image.put_pixel(x, y, color);
-        placed[x as usize][y as usize] = true;
+impl ReplayView for SingleplaceView {
+    // Only draw on pixels nobody owns yet, or that a moderator wiped or rolled back to nothing,
+    // in which case show what the moderator left behind.
+    fn placed(&mut self, replay: &CanvasReplay, placement: &Placement, previous: &PixelState) {
+        let pixel = replay.pixel(placement.x, placement.y);
+        let color = match placement.mod_action {
+            false if previous.owner == 0 => pixel.color,
+            true if pixel.owner == 0 && pixel.is_blank() => WHITE,
+            true if pixel.owner == 0 => pixel.color,
+            _ => return,
+        };
+        let (x, y) = replay.bounds().local(placement.x, placement.y);
+        self.image.put_pixel(x, y, color);
     }
+}
+
+pub async fn singleplace(source: &impl PlacementSource, event: &Event) {
+    let mut view = SingleplaceView {
+        image: white_image(&event.schedule.bounds()),
+    };
+    let mut replay = CanvasReplay::new(&event.schedule, event.mod_actions);
+    replay.run(source.placements(&event.window), &mut view).await;
 
-    image.save("output/singleplace.png").unwrap();
+    view.image.save(event.output_path("singleplace.png")).unwrap();
 }
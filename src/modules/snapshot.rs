@@ -0,0 +1,22 @@
+use crate::config::Event;
+use crate::source::PlacementSource;
+use crate::util::canvas::white_image;
+use crate::util::replay::CanvasReplay;
+
+/// Renders the canvas as it was at the end of the time window, as big as it was at that time.
+pub async fn snapshot(source: &impl PlacementSource, event: &Event) {
+    let replay = CanvasReplay::at(source, event).await;
+
+    let bounds = replay.bounds();
+    let canvas = replay.expansion();
+    let mut image = white_image(canvas);
+    for (x, y, pixel) in replay.pixels() {
+        let (x, y) = (bounds.x + x as i32, bounds.y + y as i32);
+        if !pixel.is_blank() && canvas.contains(x, y) {
+            let (x, y) = canvas.local(x, y);
+            image.put_pixel(x, y, pixel.color);
+        }
+    }
+
+    image.save(event.output_path("snapshot.png")).unwrap();
+}
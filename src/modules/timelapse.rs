@@ -1,93 +1,72 @@
-use crate::config::CANVAS_SIZES;
-use crate::util::color::hex_to_rgba;
-use crate::util::render::{blank_image_borders, extend_canvas, pixel_offset, start_ffmpeg};
-use image::{ImageBuffer, Rgba};
-use sqlx::{query_as, Pool, Sqlite};
-use std::io::Write;
-use std::process::ChildStdin;
+use crate::config::Event;
+use crate::source::{Placement, PlacementSource};
+use crate::util::encoder::Encoding;
+use crate::util::expansion::Expansion;
+use crate::util::highlight::{Highlight, Highlights};
+use crate::util::leaderboard::{Leaderboard, LeaderboardMode};
+use crate::util::render::{blank_image_borders, extend_canvas, frame_position, FrameSize, Pacing};
+use crate::util::replay::{CanvasReplay, PixelState};
+use crate::util::video::{render, Panel};
+use image::{ImageBuffer, Rgba, RgbaImage};
 
-struct Placement {
-    pub color: String,
-    pub created_at: Option<String>,
-    pub x: i64,
-    pub y: i64,
+pub struct TimelapsePanel {
+    image: ImageBuffer<Rgba<u8>, Vec<u8>>,
+    canvas: FrameSize,
+    highlights: Option<Highlights>,
 }
 
-pub async fn timelapse(
-    pool: Pool<Sqlite>,
-    fps: u8,
-    pixels_per_frame: i32,
-    min_seconds_per_frame: i32,
-) {
-    let placements = query_as!(Placement,
-            "SELECT x, y, color, strftime('%s', created_at) as created_at FROM pixel WHERE created_at > '2025-02-28 17:00:00'"
-        )
-        .fetch_all(&pool)
-        .await
-        .unwrap();
-
-    let mut image = blank_image_borders(0, false);
-
-    let (child, mut stdin) = start_ffmpeg(fps, "timelapse").expect("failed to start ffmpeg");
-
-    render_timelapse(
-        &mut image,
-        &placements,
-        &mut stdin,
-        pixels_per_frame,
-        min_seconds_per_frame,
-    )
-    .await;
-
-    drop(stdin);
-    child.wait_with_output().expect("Failed to wait on child");
-
-    println!("Done!");
+impl TimelapsePanel {
+    pub fn new(event: &Event, canvas: FrameSize, highlight: Option<Highlight>) -> Self {
+        TimelapsePanel {
+            image: blank_image_borders(&canvas, event.schedule.get(0), false),
+            canvas,
+            highlights: highlight.map(Highlights::new),
+        }
+    }
 }
 
-async fn render_timelapse(
-    image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
-    placements: &[Placement],
-    stdin: &mut ChildStdin,
-    pixels_per_frame: i32,
-    min_seconds_per_frame: i32,
-) {
-    let mut canvas_size_idx = 0;
-    let mut placement_offset = pixel_offset(canvas_size_idx);
-    let mut frame_start_time = 0;
-    let mut remaining_pixels = 0_i32;
-
-    for pixel in placements {
-        let x = pixel.x as u32;
-        let y = pixel.y as u32;
-        let timestamp: i32 = pixel.created_at.as_ref().unwrap().parse().unwrap();
+impl Panel for TimelapsePanel {
+    fn expanded(&mut self, replay: &CanvasReplay, previous: &Expansion) {
+        self.image = extend_canvas(&self.canvas, &self.image, previous, replay.expansion());
+    }
 
-        if remaining_pixels <= 0 && timestamp - frame_start_time >= min_seconds_per_frame {
-            let raw_frame = image.as_raw().clone();
-            stdin.write_all(&raw_frame).expect("Failed to write frame");
-            remaining_pixels = pixels_per_frame;
-            frame_start_time = timestamp;
+    // Draw the pixel as the replay left it, a rollback brings back an older colour.
+    fn placed(&mut self, replay: &CanvasReplay, placement: &Placement, _previous: &PixelState) {
+        let (frame_x, frame_y) = frame_position(&self.canvas, replay.expansion(), placement.x, placement.y);
+        let pixel = replay.pixel(placement.x, placement.y);
+        self.image.put_pixel(frame_x, frame_y, pixel.color);
+        if let Some(highlights) = &mut self.highlights {
+            highlights.placed(placement);
         }
+    }
 
-        remaining_pixels -= 1;
-
-        if x > CANVAS_SIZES[canvas_size_idx].0 || y > CANVAS_SIZES[canvas_size_idx].1 {
-            canvas_size_idx += 1;
-            *image = extend_canvas(image, canvas_size_idx);
-            placement_offset = pixel_offset(canvas_size_idx);
-        }
+    fn frame(&mut self, _time: i64, _expansion: &Expansion) -> &RgbaImage {
+        &self.image
+    }
 
-        image.put_pixel(
-            x + placement_offset.0,
-            y + placement_offset.1,
-            hex_to_rgba(&pixel.color),
-        );
+    fn decorated(&self) -> bool {
+        self.highlights.as_ref().is_some_and(|highlights| !highlights.is_empty())
     }
 
-    if remaining_pixels < pixels_per_frame {
-        let raw_frame = image.as_raw().clone();
-        stdin
-            .write_all(&raw_frame)
-            .expect("Failed to write last frame");
+    fn decorate(&mut self, image: &mut RgbaImage, expansion: &Expansion) {
+        if let Some(highlights) = &mut self.highlights {
+            highlights.draw(image, &self.canvas, expansion);
+        }
     }
 }
+
+pub async fn timelapse(
+    source: &impl PlacementSource,
+    event: &Event,
+    pacing: &Pacing,
+    encoding: &Encoding,
+    leaderboard: Option<(LeaderboardMode, usize)>,
+    highlight: Option<Highlight>,
+) {
+    let leaderboard = match leaderboard {
+        Some((mode, size)) => Some(Leaderboard::new(mode, size, source.users().await)),
+        None => None,
+    };
+    let panel = TimelapsePanel::new(event, encoding.canvas, highlight);
+    render(source, event, pacing, encoding, "timelapse", panel, leaderboard).await;
+}
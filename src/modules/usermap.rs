@@ -1,53 +1,24 @@
 use crate::util::canvas::white_image;
-use crate::util::color::hex_to_rgba;
-use hsv::hsv_to_rgb;
-use image::Rgba;
-use sqlx::{query, Pool, Sqlite};
-use std::collections::HashMap;
+use crate::util::color::UserColors;
+use crate::config::Event;
+use crate::source::PlacementSource;
+use crate::util::replay::CanvasReplay;
 
-pub async fn usermap(pool: Pool<Sqlite>) {
-    let placements = query!(
-        "SELECT p.x, p.y, du.discord_id, p.mod_action 
-         FROM pixel p 
-         JOIN discord_user du ON p.user_id = du.user_id 
-         WHERE p.created_at > '2025-02-28 17:00:00'"
-    )
-    .fetch_all(&pool)
-    .await
-    .unwrap();
+pub async fn usermap(source: &impl PlacementSource, event: &Event) {
+    let replay = CanvasReplay::at(source, event).await;
 
-    let mut image = white_image();
-    let mut user_colors = match std::fs::read_to_string("db/user_colors.json") {
-        Ok(json) => serde_json::from_str::<HashMap<u64, String>>(&json).unwrap(),
-        Err(_) => HashMap::new(),
-    };
+    let mut image = white_image(replay.bounds());
+    let mut user_colors = UserColors::load();
 
-    for pixel in placements {
-        let x = pixel.x as u32;
-        let y = pixel.y as u32;
-        let discord_id: u64 = pixel.discord_id.parse().unwrap();
-
-        if pixel.mod_action == 0 {
-            let color = match user_colors.get(&discord_id) {
-                Some(color) => hex_to_rgba(color),
-                None => {
-                    let h = discord_id as f64 % 360.0;
-                    let s = ((discord_id as f64 % 40.0) + 60.0) / 100.0;
-                    let v = ((discord_id as f64 % 50.0) + 50.0) / 100.0;
-                    let (r, g, b) = hsv_to_rgb(h, s, v);
-                    let color = Rgba([r, g, b, 255]);
-                    let hex = format!("#{r:02x}{g:02x}{b:02x}");
-                    user_colors.insert(discord_id, hex);
-                    color
-                }
-            };
-            image.put_pixel(x, y, color);
-        } else {
-            image.put_pixel(x, y, Rgba([255, 255, 255, 255]));
+    for (x, y, pixel) in replay.pixels() {
+        let discord_id = pixel.owner;
+        if discord_id == 0 {
+            continue;
         }
+
+        image.put_pixel(x, y, user_colors.get(discord_id));
     }
 
-    let json = serde_json::to_string(&user_colors).unwrap();
-    std::fs::write("db/user_colors.json", json).unwrap();
-    image.save("output/usermap.png").unwrap();
+    user_colors.save();
+    image.save(event.output_path("usermap.png")).unwrap();
 }
@@ -1,91 +1,61 @@
-use crate::config::CANVAS_SIZES;
+use crate::config::Event;
+use crate::source::{Placement, PlacementSource};
+use crate::util::encoder::Encoding;
+use crate::util::expansion::Expansion;
 use crate::util::render::{
-    blank_image_borders_with_colour, extend_canvas_with_colour, pixel_offset, start_ffmpeg, BLACK,
+    blank_image_borders_with_colour, extend_canvas_with_colour, frame_position, FrameSize,
+    Pacing, BLACK,
 };
-use image::{ImageBuffer, Rgba};
-use sqlx::{query_as, Pool, Sqlite};
-use std::io::Write;
-use std::process::ChildStdin;
-
-struct Placement {
-    pub created_at: Option<String>,
-    pub x: i64,
-    pub y: i64,
-}
+use crate::util::replay::{CanvasReplay, PixelState};
+use crate::util::video::{render, Panel};
+use image::{ImageBuffer, Rgba, RgbaImage};
 
 const VIRGIN_COLOUR: Rgba<u8> = Rgba([255, 0, 255, 255]);
 
-pub async fn timelapse(
-    pool: Pool<Sqlite>,
-    fps: u8,
-    pixels_per_frame: i32,
-    min_seconds_per_frame: i32,
-) {
-    let placements = query_as!(Placement,
-            "SELECT x, y, strftime('%s', created_at) as created_at FROM pixel WHERE created_at > '2025-02-28 17:00:00'"
-        )
-        .fetch_all(&pool)
-        .await
-        .unwrap();
-
-    let mut image = blank_image_borders_with_colour(0, VIRGIN_COLOUR, BLACK);
-
-    let (child, mut stdin) = start_ffmpeg(fps, "virginmap").expect("failed to start ffmpeg");
-
-    render_timelapse(
-        &mut image,
-        &placements,
-        &mut stdin,
-        pixels_per_frame,
-        min_seconds_per_frame,
-    )
-    .await;
-
-    drop(stdin);
-    child.wait_with_output().expect("Failed to wait on child");
-
-    println!("Done!");
+pub struct VirginmapPanel {
+    image: ImageBuffer<Rgba<u8>, Vec<u8>>,
+    canvas: FrameSize,
 }
 
-async fn render_timelapse(
-    image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
-    placements: &[Placement],
-    stdin: &mut ChildStdin,
-    pixels_per_frame: i32,
-    min_seconds_per_frame: i32,
-) {
-    let mut canvas_size_idx = 0;
-    let mut placement_offset = pixel_offset(canvas_size_idx);
-    let mut frame_start_time = 0;
-    let mut remaining_pixels = 0_i32;
-
-    for pixel in placements {
-        let x = pixel.x as u32;
-        let y = pixel.y as u32;
-        let timestamp: i32 = pixel.created_at.as_ref().unwrap().parse().unwrap();
-
-        if remaining_pixels <= 0 && timestamp - frame_start_time >= min_seconds_per_frame {
-            let raw_frame = image.as_raw().clone();
-            stdin.write_all(&raw_frame).expect("Failed to write frame");
-            remaining_pixels = pixels_per_frame;
-            frame_start_time = timestamp;
+impl VirginmapPanel {
+    pub fn new(event: &Event, canvas: FrameSize) -> Self {
+        VirginmapPanel {
+            image: blank_image_borders_with_colour(&canvas, event.schedule.get(0), VIRGIN_COLOUR, BLACK),
+            canvas,
         }
+    }
+}
 
-        remaining_pixels -= 1;
+impl Panel for VirginmapPanel {
+    fn expanded(&mut self, replay: &CanvasReplay, previous: &Expansion) {
+        self.image = extend_canvas_with_colour(
+            &self.canvas,
+            &self.image,
+            previous,
+            replay.expansion(),
+            VIRGIN_COLOUR,
+            BLACK,
+        );
+    }
 
-        if x > CANVAS_SIZES[canvas_size_idx].0 || y > CANVAS_SIZES[canvas_size_idx].1 {
-            canvas_size_idx += 1;
-            *image = extend_canvas_with_colour(image, canvas_size_idx, VIRGIN_COLOUR, BLACK);
-            placement_offset = pixel_offset(canvas_size_idx);
+    fn placed(&mut self, replay: &CanvasReplay, placement: &Placement, previous: &PixelState) {
+        if previous.first_touch.is_none() {
+            let (frame_x, frame_y) = frame_position(&self.canvas, replay.expansion(), placement.x, placement.y);
+            self.image.put_pixel(frame_x, frame_y, BLACK);
         }
-
-        image.put_pixel(x + placement_offset.0, y + placement_offset.1, BLACK);
     }
 
-    if remaining_pixels < pixels_per_frame {
-        let raw_frame = image.as_raw().clone();
-        stdin
-            .write_all(&raw_frame)
-            .expect("Failed to write last frame");
+    fn frame(&mut self, _time: i64, _expansion: &Expansion) -> &RgbaImage {
+        &self.image
     }
 }
+
+pub async fn timelapse(
+    source: &impl PlacementSource,
+    event: &Event,
+    pacing: &Pacing,
+    encoding: &Encoding,
+) {
+    let panel = VirginmapPanel::new(event, encoding.canvas);
+    render(source, event, pacing, encoding, "virginmap", panel, None).await;
+}
@@ -0,0 +1,283 @@
+use crate::source::{Placement, PlacementSource, User};
+use crate::util::color::rgba_to_hex;
+use crate::util::time::TimeWindow;
+use futures_util::stream::{self, BoxStream};
+use futures_util::StreamExt;
+use image::Rgba;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"CNVCACHE";
+const VERSION: u32 = 1;
+/// The columns in the order they are stored.
+const COLUMNS: [&str; 6] = ["timestamp", "x", "y", "color", "user", "mod_action"];
+
+/// A compiled copy of another source, written by `canvas cache`.
+///
+/// After a header with the source fingerprint, the palette and the interned
+/// users, the placements are stored column by column: timestamps (i64), x and
+/// y (i32), palette indices (u8), user indices (u32) and mod action flags (u8),
+/// all little endian.
+pub struct CacheSource {
+    path: PathBuf,
+    fingerprint: String,
+    palette: Vec<Rgba<u8>>,
+    /// Every user id seen in the placements, `None` for the ones the source has no user for.
+    users: Vec<(u64, Option<User>)>,
+    count: u64,
+    last_placement: Option<i64>,
+    columns_start: u64,
+}
+
+struct Columns {
+    timestamp: BufReader<File>,
+    x: BufReader<File>,
+    y: BufReader<File>,
+    color: BufReader<File>,
+    user: BufReader<File>,
+    mod_action: BufReader<File>,
+}
+
+impl CacheSource {
+    /// Reads the cache header, or returns `None` if there is no usable cache at `path`.
+    pub fn open(path: PathBuf) -> Option<Self> {
+        let mut reader = BufReader::new(File::open(&path).ok()?);
+        let mut magic = [0; 8];
+        if reader.read_exact(&mut magic).is_err()
+            || &magic != MAGIC
+            || u32::from_le_bytes(read_bytes(&mut reader)) != VERSION
+        {
+            eprintln!("Ignoring {}, it isn't a cache from this version", path.display());
+            return None;
+        }
+
+        let fingerprint = read_string(&mut reader);
+        let palette = (0..u32::from_le_bytes(read_bytes(&mut reader)))
+            .map(|_| Rgba(read_bytes(&mut reader)))
+            .collect();
+        let users = (0..u32::from_le_bytes(read_bytes(&mut reader)))
+            .map(|_| {
+                let id = u64::from_le_bytes(read_bytes(&mut reader));
+                let user = match read_bytes::<1>(&mut reader)[0] {
+                    0 => None,
+                    flag => Some(User {
+                        name: read_string(&mut reader),
+                        banned: flag == 2,
+                    }),
+                };
+                (id, user)
+            })
+            .collect();
+        let count = u64::from_le_bytes(read_bytes(&mut reader));
+        let last_placement = i64::from_le_bytes(read_bytes(&mut reader));
+        let columns_start = reader.stream_position().unwrap();
+
+        Some(CacheSource {
+            path,
+            fingerprint,
+            palette,
+            users,
+            count,
+            last_placement: (count > 0).then_some(last_placement),
+            columns_start,
+        })
+    }
+
+    /// Compiles every placement and user of `source` into a cache at `path`.
+    /// Colours are stored as indices into `palette`, extended with any colour it is missing.
+    pub async fn write(source: &impl PlacementSource, path: &Path, palette: &[Rgba<u8>]) {
+        let fingerprint = source.fingerprint().await;
+        let mut users: Vec<(u64, Option<User>)> = source
+            .users()
+            .await
+            .into_iter()
+            .map(|(id, user)| (id, Some(user)))
+            .collect();
+        users.sort_by_key(|(id, _)| *id);
+        let mut user_index: HashMap<u64, u32> = users
+            .iter()
+            .enumerate()
+            .map(|(idx, (id, _))| (*id, idx as u32))
+            .collect();
+        let mut palette = palette.to_vec();
+
+        // The columns go to files of their own next to the cache until the header is known,
+        // so the placements never have to fit in memory.
+        let column_paths = COLUMNS.map(|column| {
+            let mut column_path = path.as_os_str().to_owned();
+            column_path.push(format!(".{column}.tmp"));
+            PathBuf::from(column_path)
+        });
+        let mut columns = column_paths.each_ref().map(|column_path| {
+            let file = File::create(column_path)
+                .unwrap_or_else(|e| panic!("Failed to create {}: {e}", column_path.display()));
+            BufWriter::new(file)
+        });
+        let mut count = 0_u64;
+        let mut last_placement = i64::MIN;
+
+        // The time window is applied when reading the cache, so take everything.
+        let window = TimeWindow {
+            since: 0,
+            until: None,
+        };
+        let mut placements = source.placements(&window);
+        while let Some(pixel) = placements.next().await {
+            let color = match palette.iter().position(|color| *color == pixel.color) {
+                Some(idx) => idx,
+                None => {
+                    palette.push(pixel.color);
+                    palette.len() - 1
+                }
+            };
+            assert!(color <= u8::MAX as usize, "too many colours to cache, the limit is 256");
+            let user = *user_index.entry(pixel.user).or_insert_with(|| {
+                users.push((pixel.user, None));
+                (users.len() - 1) as u32
+            });
+
+            let [timestamps, xs, ys, colors, user_indices, mod_actions] = &mut columns;
+            timestamps.write_all(&pixel.timestamp.to_le_bytes()).unwrap();
+            xs.write_all(&pixel.x.to_le_bytes()).unwrap();
+            ys.write_all(&pixel.y.to_le_bytes()).unwrap();
+            colors.write_all(&[color as u8]).unwrap();
+            user_indices.write_all(&user.to_le_bytes()).unwrap();
+            mod_actions.write_all(&[pixel.mod_action as u8]).unwrap();
+            count += 1;
+            last_placement = last_placement.max(pixel.timestamp);
+        }
+        drop(placements);
+        for column in columns {
+            column.into_inner().unwrap();
+        }
+
+        // Write next to the cache and rename it, so an interrupted run never leaves a broken cache behind.
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let file = File::create(&tmp_path)
+            .unwrap_or_else(|e| panic!("Failed to create {}: {e}", path.display()));
+        let mut writer = BufWriter::new(file);
+
+        writer.write_all(MAGIC).unwrap();
+        writer.write_all(&VERSION.to_le_bytes()).unwrap();
+        write_string(&mut writer, &fingerprint);
+        writer.write_all(&(palette.len() as u32).to_le_bytes()).unwrap();
+        for color in &palette {
+            writer.write_all(&color.0).unwrap();
+        }
+        writer.write_all(&(users.len() as u32).to_le_bytes()).unwrap();
+        for (id, user) in &users {
+            writer.write_all(&id.to_le_bytes()).unwrap();
+            match user {
+                None => writer.write_all(&[0]).unwrap(),
+                Some(user) => {
+                    writer.write_all(&[if user.banned { 2 } else { 1 }]).unwrap();
+                    write_string(&mut writer, &user.name);
+                }
+            }
+        }
+        writer.write_all(&count.to_le_bytes()).unwrap();
+        writer.write_all(&last_placement.to_le_bytes()).unwrap();
+        for column_path in &column_paths {
+            io::copy(&mut File::open(column_path).unwrap(), &mut writer).unwrap();
+            fs::remove_file(column_path).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+        fs::rename(&tmp_path, path).unwrap();
+
+        println!(
+            "Cached {count} placements and {} users in {}",
+            users.len(),
+            path.display()
+        );
+    }
+
+    fn columns(&self) -> Columns {
+        let mut offset = self.columns_start;
+        let mut column = |width: u64| {
+            let mut file = File::open(&self.path).unwrap();
+            file.seek(SeekFrom::Start(offset)).unwrap();
+            offset += width * self.count;
+            BufReader::new(file)
+        };
+        Columns {
+            timestamp: column(8),
+            x: column(4),
+            y: column(4),
+            color: column(1),
+            user: column(4),
+            mod_action: column(1),
+        }
+    }
+}
+
+impl PlacementSource for CacheSource {
+    fn placements(&self, window: &TimeWindow) -> BoxStream<'_, Placement> {
+        let window = window.clone();
+        let mut columns = self.columns();
+        let placements = (0..self.count)
+            .map(move |_| {
+                let user = u32::from_le_bytes(read_bytes(&mut columns.user));
+                Placement {
+                    timestamp: i64::from_le_bytes(read_bytes(&mut columns.timestamp)),
+                    x: i32::from_le_bytes(read_bytes(&mut columns.x)),
+                    y: i32::from_le_bytes(read_bytes(&mut columns.y)),
+                    color: self.palette[read_bytes::<1>(&mut columns.color)[0] as usize],
+                    user: self.users[user as usize].0,
+                    mod_action: read_bytes::<1>(&mut columns.mod_action)[0] == 1,
+                }
+            })
+            .filter(move |pixel| window.contains(pixel.timestamp));
+        stream::iter(placements).boxed()
+    }
+
+    async fn users(&self) -> HashMap<u64, User> {
+        self.users
+            .iter()
+            .filter_map(|(id, user)| user.clone().map(|user| (*id, user)))
+            .collect()
+    }
+
+    async fn last_placement(&self) -> Option<i64> {
+        self.last_placement
+    }
+
+    async fn colors(&self) -> HashMap<String, u64> {
+        let mut counts = vec![0; self.palette.len()];
+        let mut colors = self.columns().color;
+        for _ in 0..self.count {
+            counts[read_bytes::<1>(&mut colors)[0] as usize] += 1;
+        }
+        self.palette
+            .iter()
+            .zip(counts)
+            .filter(|(_, count)| *count > 0)
+            .map(|(color, count)| (rgba_to_hex(*color), count))
+            .collect()
+    }
+
+    async fn fingerprint(&self) -> String {
+        self.fingerprint.clone()
+    }
+}
+
+fn read_bytes<const N: usize>(reader: &mut impl Read) -> [u8; N] {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes).expect("Truncated cache file");
+    bytes
+}
+
+fn read_string(reader: &mut impl Read) -> String {
+    let len = u32::from_le_bytes(read_bytes(reader));
+    let mut bytes = vec![0; len as usize];
+    reader.read_exact(&mut bytes).expect("Truncated cache file");
+    String::from_utf8(bytes).expect("Invalid string in cache file")
+}
+
+fn write_string(writer: &mut impl Write, text: &str) {
+    writer.write_all(&(text.len() as u32).to_le_bytes()).unwrap();
+    writer.write_all(text.as_bytes()).unwrap();
+}
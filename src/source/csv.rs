@@ -0,0 +1,189 @@
+use crate::source::{file_fingerprint, Placement, PlacementSource, User};
+use crate::util::color::hex_to_rgba;
+use crate::util::time::{parse_timestamp, TimeWindow};
+use futures_util::stream::{self, BoxStream};
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+
+/// The public r/place dumps, with `timestamp`, `user_id` (or `user`),
+/// `pixel_color` and `coordinate` columns, sorted by time.
+/// Rectangles and circles in `coordinate` are moderator actions.
+pub struct CsvSource {
+    path: PathBuf,
+}
+
+struct Columns {
+    timestamp: usize,
+    user: usize,
+    color: usize,
+    coordinate: usize,
+}
+
+struct Row {
+    timestamp: i64,
+    user: String,
+    color: String,
+    coordinate: String,
+}
+
+impl CsvSource {
+    pub fn open(path: PathBuf) -> Self {
+        CsvSource { path }
+    }
+
+    fn rows(&self) -> impl Iterator<Item = Row> {
+        let file = File::open(&self.path)
+            .unwrap_or_else(|e| panic!("Failed to open {}: {e}", self.path.display()));
+        let mut lines = BufReader::new(file).lines().map(|line| line.unwrap());
+        let header = lines.next().expect("csv file is empty");
+        let columns = Columns::from_header(&header);
+
+        lines
+            .filter(|line| !line.is_empty())
+            .map(move |line| columns.parse(&line))
+    }
+}
+
+impl Columns {
+    fn from_header(header: &str) -> Self {
+        let names = split_line(header);
+        let find = |candidates: &[&str]| {
+            names
+                .iter()
+                .position(|name| candidates.contains(name))
+                .unwrap_or_else(|| panic!("csv header is missing a {} column", candidates[0]))
+        };
+
+        Columns {
+            timestamp: find(&["timestamp"]),
+            user: find(&["user_id", "user"]),
+            color: find(&["pixel_color", "color"]),
+            coordinate: find(&["coordinate"]),
+        }
+    }
+
+    fn parse(&self, line: &str) -> Row {
+        let fields = split_line(line);
+        Row {
+            timestamp: parse_timestamp(fields[self.timestamp])
+                .unwrap_or_else(|| panic!("invalid timestamp in '{line}'")),
+            user: fields[self.user].to_string(),
+            color: fields[self.color].to_string(),
+            coordinate: fields[self.coordinate].to_string(),
+        }
+    }
+}
+
+impl Row {
+    fn placements(&self) -> Vec<Placement> {
+        let color = hex_to_rgba(&self.color);
+        let placement = |x, y, mod_action| Placement {
+            x,
+            y,
+            color,
+            timestamp: self.timestamp,
+            user: user_id(&self.user),
+            mod_action,
+        };
+
+        // {X: 10, Y: 20, R: 5}
+        if self.coordinate.starts_with('{') {
+            let values = numbers(&self.coordinate);
+            let (center_x, center_y, radius) = (values[0], values[1], values[2]);
+            let mut placements = vec![];
+            for x in center_x - radius..=center_x + radius {
+                for y in center_y - radius..=center_y + radius {
+                    let (dx, dy) = (x - center_x, y - center_y);
+                    if dx * dx + dy * dy <= radius * radius {
+                        placements.push(placement(x, y, true));
+                    }
+                }
+            }
+            return placements;
+        }
+
+        match numbers(&self.coordinate)[..] {
+            [x, y] => vec![placement(x, y, false)],
+            [x1, y1, x2, y2] => (x1.min(x2)..=x1.max(x2))
+                .flat_map(|x| (y1.min(y2)..=y1.max(y2)).map(move |y| (x, y)))
+                .map(|(x, y)| placement(x, y, true))
+                .collect(),
+            _ => panic!("invalid coordinate '{}'", self.coordinate),
+        }
+    }
+}
+
+impl PlacementSource for CsvSource {
+    fn placements(&self, window: &TimeWindow) -> BoxStream<'_, Placement> {
+        let window = window.clone();
+        let placements = self
+            .rows()
+            .filter(move |row| window.contains(row.timestamp))
+            .flat_map(|row| row.placements());
+        stream::iter(placements).boxed()
+    }
+
+    async fn users(&self) -> HashMap<u64, User> {
+        let mut users = HashMap::new();
+        for row in self.rows() {
+            users.entry(user_id(&row.user)).or_insert_with(|| User {
+                name: row.user.chars().filter(char::is_ascii_alphanumeric).take(12).collect(),
+                banned: false,
+            });
+        }
+        users
+    }
+
+    async fn last_placement(&self) -> Option<i64> {
+        self.rows().map(|row| row.timestamp).max()
+    }
+
+    async fn colors(&self) -> HashMap<String, u64> {
+        let mut colors = HashMap::new();
+        for row in self.rows() {
+            *colors.entry(row.color).or_insert(0) += 1;
+        }
+        colors
+    }
+
+    async fn fingerprint(&self) -> String {
+        file_fingerprint(&self.path)
+    }
+}
+
+/// Splits a csv line on commas, keeping quoted fields together.
+fn split_line(line: &str) -> Vec<&str> {
+    let mut fields = vec![];
+    let mut start = 0;
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                fields.push(line[start..i].trim_matches('"'));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    fields.push(line[start..].trim_matches('"'));
+    fields
+}
+
+fn numbers(text: &str) -> Vec<i32> {
+    text.split(|c: char| !c.is_ascii_digit() && c != '-')
+        .filter(|number| !number.is_empty())
+        .map(|number| number.parse().unwrap())
+        .collect()
+}
+
+/// r/place users are hashes, so turn them into stable ids with FNV-1a.
+fn user_id(user: &str) -> u64 {
+    let hash = user.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    hash.max(1)
+}
@@ -0,0 +1,191 @@
+use crate::source::{Malformed, Placement, PlacementSource, User};
+use crate::util::replay::ModActions;
+use crate::util::time::TimeWindow;
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use std::collections::{HashMap, HashSet};
+
+/// Another source, as if some users never placed anything. Whatever they drew over
+/// shows through again, since their placements are left out of the replay, along with
+/// the moderator actions that cleaned them up.
+pub struct FilteredSource<S> {
+    source: S,
+    excluded: HashSet<u64>,
+    /// How the replay applies moderator actions, to know whose placement each one lands on.
+    mod_actions: ModActions,
+}
+
+impl<S: PlacementSource> FilteredSource<S> {
+    pub fn new(source: S, excluded: HashSet<u64>, mod_actions: ModActions) -> Self {
+        FilteredSource {
+            source,
+            excluded,
+            mod_actions,
+        }
+    }
+}
+
+/// Who owns every pixel in the replay of the full source, the owners under them too for rollbacks.
+struct Owners {
+    mod_actions: ModActions,
+    pixels: HashMap<(i32, i32), Vec<u64>>,
+}
+
+impl Owners {
+    /// Whether the placement is kept. Moderator actions on an excluded user's placement are
+    /// left out with it, or they would wipe or roll back whatever shows through instead.
+    fn keep(&mut self, pixel: &Placement, excluded: &HashSet<u64>) -> bool {
+        let owners = self.pixels.entry((pixel.x, pixel.y)).or_default();
+        let showing = owners.last().copied().unwrap_or(0);
+        match (pixel.mod_action, self.mod_actions) {
+            (false, ModActions::Wipe) => *owners = vec![pixel.user],
+            (false, ModActions::Rollback) => owners.push(pixel.user),
+            (true, ModActions::Wipe) => *owners = vec![0],
+            (true, ModActions::Rollback) => {
+                owners.pop();
+            }
+        }
+        let cleans_up = pixel.mod_action && excluded.contains(&showing);
+        !excluded.contains(&pixel.user) && !cleans_up
+    }
+}
+
+impl<S: PlacementSource> PlacementSource for FilteredSource<S> {
+    fn placements(&self, window: &TimeWindow) -> BoxStream<'_, Placement> {
+        let excluded = &self.excluded;
+        let mut owners = Owners {
+            mod_actions: self.mod_actions,
+            pixels: HashMap::new(),
+        };
+        self.source
+            .placements(window)
+            .filter(move |pixel| std::future::ready(owners.keep(pixel, excluded)))
+            .boxed()
+    }
+
+    async fn users(&self) -> HashMap<u64, User> {
+        self.source.users().await
+    }
+
+    async fn last_placement(&self) -> Option<i64> {
+        self.source.last_placement().await
+    }
+
+    async fn colors(&self) -> HashMap<String, u64> {
+        self.source.colors().await
+    }
+
+    fn malformed(&self) -> BoxStream<'_, Malformed> {
+        self.source.malformed()
+    }
+
+    // Nothing derived from the full source applies once users are left out.
+    async fn fingerprint(&self) -> String {
+        let mut excluded: Vec<&u64> = self.excluded.iter().collect();
+        excluded.sort();
+        format!("{} without {excluded:?}", self.source.fingerprint().await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::expansion::{Expansion, ExpansionSchedule};
+    use crate::util::replay::{CanvasReplay, PixelState};
+    use futures_util::stream;
+    use image::Rgba;
+
+    const LEGIT: u64 = 1;
+    const GRIEFER: u64 = 2;
+    const MODERATOR: u64 = 3;
+    const ART: Rgba<u8> = Rgba([0, 0, 255, 255]);
+    const GRIEF: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const WIPE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    struct Placements(Vec<Placement>);
+
+    impl PlacementSource for Placements {
+        fn placements(&self, _window: &TimeWindow) -> BoxStream<'_, Placement> {
+            stream::iter(self.0.clone()).boxed()
+        }
+
+        async fn users(&self) -> HashMap<u64, User> {
+            HashMap::new()
+        }
+
+        async fn last_placement(&self) -> Option<i64> {
+            self.0.last().map(|placement| placement.timestamp)
+        }
+
+        async fn colors(&self) -> HashMap<String, u64> {
+            HashMap::new()
+        }
+
+        async fn fingerprint(&self) -> String {
+            String::new()
+        }
+    }
+
+    fn placement(timestamp: i64, user: u64, color: Rgba<u8>, mod_action: bool) -> Placement {
+        Placement {
+            x: 0,
+            y: 0,
+            color,
+            timestamp,
+            user,
+            mod_action,
+        }
+    }
+
+    /// The pixel after replaying `placements` without the griefer.
+    async fn without_griefer(placements: Vec<Placement>, mod_actions: ModActions) -> PixelState {
+        let filtered = FilteredSource::new(Placements(placements), HashSet::from([GRIEFER]), mod_actions);
+        let schedule = ExpansionSchedule::new(vec![Expansion {
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+            at: 0,
+        }]);
+        let window = TimeWindow {
+            since: 0,
+            until: None,
+        };
+        let mut replay = CanvasReplay::new(&schedule, mod_actions);
+        replay.run(filtered.placements(&window), &mut ()).await;
+        *replay.pixel(0, 0)
+    }
+
+    /// Art, grief over it and a moderator cleaning it up.
+    fn cleaned_up_grief() -> Vec<Placement> {
+        vec![
+            placement(1, LEGIT, ART, false),
+            placement(2, GRIEFER, GRIEF, false),
+            placement(3, MODERATOR, WIPE, true),
+        ]
+    }
+
+    #[tokio::test]
+    async fn rollback_of_excluded_placement_keeps_art() {
+        let pixel = without_griefer(cleaned_up_grief(), ModActions::Rollback).await;
+        assert_eq!((pixel.color, pixel.owner), (ART, LEGIT));
+    }
+
+    #[tokio::test]
+    async fn wipe_of_excluded_placement_keeps_art() {
+        let pixel = without_griefer(cleaned_up_grief(), ModActions::Wipe).await;
+        assert_eq!((pixel.color, pixel.owner), (ART, LEGIT));
+    }
+
+    #[tokio::test]
+    async fn moderator_actions_on_kept_placements_stay() {
+        let placements = vec![
+            placement(1, LEGIT, ART, false),
+            placement(2, MODERATOR, WIPE, true),
+        ];
+        let pixel = without_griefer(placements.clone(), ModActions::Wipe).await;
+        assert_eq!((pixel.color, pixel.owner), (WIPE, 0));
+        let pixel = without_griefer(placements, ModActions::Rollback).await;
+        assert!(pixel.is_blank());
+    }
+}
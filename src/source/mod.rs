@@ -0,0 +1,129 @@
+pub mod cache;
+pub mod csv;
+pub mod filtered;
+pub mod ndjson;
+pub mod postgres;
+pub mod sqlite;
+
+use crate::util::time::TimeWindow;
+use futures_util::stream::{self, BoxStream};
+use futures_util::StreamExt;
+use image::Rgba;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// A single pixel placement, independent of where the event data came from.
+#[derive(Clone, Debug)]
+pub struct Placement {
+    pub x: i32,
+    pub y: i32,
+    pub color: Rgba<u8>,
+    pub timestamp: i64,
+    /// 0 when the placement can't be linked to a user.
+    pub user: u64,
+    pub mod_action: bool,
+}
+
+/// A row a source can't read, which reading the placements or users would panic on.
+pub struct Malformed {
+    /// What is wrong with it, the same for every row with the same problem.
+    pub problem: &'static str,
+    pub example: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct User {
+    pub name: String,
+    pub banned: bool,
+}
+
+pub trait PlacementSource {
+    /// Streams every placement inside the window, in the order they were placed.
+    fn placements(&self, window: &TimeWindow) -> BoxStream<'_, Placement>;
+
+    async fn users(&self) -> HashMap<u64, User>;
+
+    async fn last_placement(&self) -> Option<i64>;
+
+    /// How many placements use each colour, as the source writes it.
+    async fn colors(&self) -> HashMap<String, u64>;
+
+    /// Changes whenever the placements or users change, to tell if a cache is still valid.
+    async fn fingerprint(&self) -> String;
+
+    /// Every row that can't be read, for `canvas check` to report before reading the rest.
+    fn malformed(&self) -> BoxStream<'_, Malformed> {
+        stream::empty().boxed()
+    }
+}
+
+/// Size and modification time, for sources backed by a single file.
+pub fn file_fingerprint(path: &Path) -> String {
+    let metadata = fs::metadata(path)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {e}", path.display()));
+    let modified = metadata
+        .modified()
+        .unwrap()
+        .duration_since(UNIX_EPOCH)
+        .unwrap();
+    format!("{}:{}", metadata.len(), modified.as_nanos())
+}
+
+pub enum SourceKind {
+    Sqlite(String),
+    Postgres(String),
+    Csv(PathBuf),
+    Ndjson(PathBuf),
+}
+
+impl SourceKind {
+    /// Picks a source from a `sqlite://`, `postgres://`, `csv://` or `ndjson://` url, or from a file extension.
+    pub fn parse(url: &str) -> SourceKind {
+        if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+            return SourceKind::Postgres(url.to_string());
+        }
+        if let Some(path) = url.strip_prefix("csv://") {
+            return SourceKind::Csv(PathBuf::from(path));
+        }
+        if let Some(path) = url.strip_prefix("ndjson://") {
+            return SourceKind::Ndjson(PathBuf::from(path));
+        }
+        if url.ends_with(".csv") {
+            return SourceKind::Csv(PathBuf::from(url));
+        }
+        if url.ends_with(".ndjson") || url.ends_with(".jsonl") {
+            return SourceKind::Ndjson(PathBuf::from(url));
+        }
+        SourceKind::Sqlite(url.to_string())
+    }
+
+    /// Where files derived from this source go, like the cache and the keyframe index:
+    /// next to the source file, or named after the database in the working directory for Postgres.
+    pub fn sidecar_path(&self, extension: &str) -> Option<PathBuf> {
+        let path = match self {
+            SourceKind::Sqlite(url) => {
+                let path = url
+                    .trim_start_matches("sqlite://")
+                    .trim_start_matches("sqlite:")
+                    .split('?')
+                    .next()
+                    .unwrap();
+                if path.is_empty() || path == ":memory:" {
+                    return None;
+                }
+                PathBuf::from(path)
+            }
+            SourceKind::Postgres(url) => {
+                let database = url.split('?').next().unwrap().rsplit('/').next().unwrap();
+                PathBuf::from(database)
+            }
+            SourceKind::Csv(path) | SourceKind::Ndjson(path) => path.clone(),
+        };
+        let mut path = path.into_os_string();
+        path.push(".");
+        path.push(extension);
+        Some(PathBuf::from(path))
+    }
+}
@@ -0,0 +1,117 @@
+use crate::source::{file_fingerprint, Placement, PlacementSource, User};
+use crate::util::color::hex_to_rgba;
+use crate::util::time::{parse_timestamp, TimeWindow};
+use futures_util::stream::{self, BoxStream};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+
+/// One placement per line, sorted by time:
+/// `{"x": 1, "y": 2, "color": "#FF4500", "timestamp": 1740762000, "user": 42}`
+/// with optional `username`, `banned` and `mod_action` fields.
+pub struct NdjsonSource {
+    path: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct Line {
+    x: i32,
+    y: i32,
+    color: String,
+    timestamp: Timestamp,
+    user: u64,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    banned: bool,
+    #[serde(default)]
+    mod_action: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Timestamp {
+    Unix(i64),
+    Text(String),
+}
+
+impl Line {
+    fn timestamp(&self) -> i64 {
+        match &self.timestamp {
+            Timestamp::Unix(timestamp) => *timestamp,
+            Timestamp::Text(text) => {
+                parse_timestamp(text).unwrap_or_else(|| panic!("invalid timestamp '{text}'"))
+            }
+        }
+    }
+}
+
+impl NdjsonSource {
+    pub fn open(path: PathBuf) -> Self {
+        NdjsonSource { path }
+    }
+
+    fn lines(&self) -> impl Iterator<Item = Line> {
+        let file = File::open(&self.path)
+            .unwrap_or_else(|e| panic!("Failed to open {}: {e}", self.path.display()));
+        BufReader::new(file)
+            .lines()
+            .map(|line| line.unwrap())
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str(&line).unwrap_or_else(|e| panic!("Invalid line '{line}': {e}"))
+            })
+    }
+}
+
+impl PlacementSource for NdjsonSource {
+    fn placements(&self, window: &TimeWindow) -> BoxStream<'_, Placement> {
+        let window = window.clone();
+        let placements = self
+            .lines()
+            .filter(move |line| window.contains(line.timestamp()))
+            .map(|line| Placement {
+                x: line.x,
+                y: line.y,
+                color: hex_to_rgba(&line.color),
+                timestamp: line.timestamp(),
+                user: line.user,
+                mod_action: line.mod_action,
+            });
+        stream::iter(placements).boxed()
+    }
+
+    async fn users(&self) -> HashMap<u64, User> {
+        let mut users: HashMap<u64, User> = HashMap::new();
+        for line in self.lines() {
+            let user = users.entry(line.user).or_insert_with(|| User {
+                name: line.user.to_string(),
+                banned: false,
+            });
+            if let Some(username) = line.username {
+                user.name = username;
+            }
+            user.banned |= line.banned;
+        }
+        users
+    }
+
+    async fn last_placement(&self) -> Option<i64> {
+        self.lines().map(|line| line.timestamp()).max()
+    }
+
+    async fn colors(&self) -> HashMap<String, u64> {
+        let mut colors = HashMap::new();
+        for line in self.lines() {
+            *colors.entry(line.color).or_insert(0) += 1;
+        }
+        colors
+    }
+
+    async fn fingerprint(&self) -> String {
+        file_fingerprint(&self.path)
+    }
+}
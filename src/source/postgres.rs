@@ -0,0 +1,127 @@
+use crate::source::sqlite::{malformed_created_at, malformed_discord_id, parse_created_at, PixelRow};
+use crate::source::{Malformed, Placement, PlacementSource, User};
+use crate::util::time::TimeWindow;
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{Executor, PgPool};
+use std::collections::HashMap;
+
+/// The osucord canvas schema on Postgres. The query macros can only check against one
+/// database, so these are runtime queries, casting every column to what SQLite returns.
+pub struct PostgresSource {
+    pool: PgPool,
+}
+
+impl PostgresSource {
+    pub async fn connect(url: &str) -> Self {
+        // `CAST(created_at AS TEXT)` on a timestamptz uses the session time zone.
+        let pool = PgPoolOptions::new()
+            .after_connect(|conn, _meta| {
+                Box::pin(async move {
+                    conn.execute("SET TIME ZONE 'UTC'").await?;
+                    Ok(())
+                })
+            })
+            .connect(url)
+            .await
+            .unwrap();
+        PostgresSource { pool }
+    }
+}
+
+impl PlacementSource for PostgresSource {
+    fn placements(&self, window: &TimeWindow) -> BoxStream<'_, Placement> {
+        // There is no rowid, the serial id keeps placements in the same second in insertion order.
+        sqlx::query_as::<_, PixelRow>(
+            r#"SELECT CAST(p.x AS BIGINT) as x, CAST(p.y AS BIGINT) as y, p.color,
+                CAST(p.created_at AS TEXT) as created_at, CAST(du.discord_id AS TEXT) as discord_id,
+                CAST(CAST(p.mod_action AS INTEGER) AS BIGINT) as mod_action
+            FROM pixel p
+            LEFT JOIN discord_user du ON p.user_id = du.user_id
+            WHERE p.created_at > CAST($1 AS TIMESTAMP) AND p.created_at <= CAST($2 AS TIMESTAMP)
+            ORDER BY p.created_at, p.id"#,
+        )
+        .bind(window.since_sql())
+        .bind(window.until_sql())
+        .fetch(&self.pool)
+        .map(|pixel| pixel.unwrap())
+        .map(PixelRow::placement)
+        .boxed()
+    }
+
+    async fn users(&self) -> HashMap<u64, User> {
+        sqlx::query_as::<_, (String, String, Option<i32>)>(
+            r#"SELECT CAST(du.discord_id AS TEXT), du.discord_username, CAST(u.is_banned AS INTEGER)
+            FROM discord_user du
+            LEFT JOIN "user" u ON du.user_id = u.user_id"#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(discord_id, name, is_banned)| {
+            let user = User {
+                name,
+                banned: is_banned.unwrap_or(0) != 0,
+            };
+            (discord_id.parse().unwrap(), user)
+        })
+        .collect()
+    }
+
+    async fn last_placement(&self) -> Option<i64> {
+        sqlx::query_scalar::<_, Option<String>>("SELECT CAST(MAX(created_at) AS TEXT) FROM pixel")
+            .fetch_one(&self.pool)
+            .await
+            .unwrap()
+            .map(|created_at| parse_created_at(&created_at))
+    }
+
+    async fn colors(&self) -> HashMap<String, u64> {
+        sqlx::query_as::<_, (String, i64)>("SELECT color, COUNT(*) FROM pixel GROUP BY color")
+            .fetch_all(&self.pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(color, count)| (color, count as u64))
+            .collect()
+    }
+
+    fn malformed(&self) -> BoxStream<'_, Malformed> {
+        let users = sqlx::query_scalar::<_, String>("SELECT CAST(discord_id AS TEXT) FROM discord_user")
+            .fetch(&self.pool)
+            .filter_map(|discord_id| std::future::ready(malformed_discord_id(discord_id.unwrap())));
+        let pixels = sqlx::query_as::<_, (i64, Option<String>)>(
+            "SELECT CAST(id AS BIGINT), CAST(created_at AS TEXT) FROM pixel ORDER BY id",
+        )
+        .fetch(&self.pool)
+        .filter_map(|row| {
+            let (row, created_at) = row.unwrap();
+            std::future::ready(malformed_created_at(row, created_at))
+        });
+        users.chain(pixels).boxed()
+    }
+
+    async fn fingerprint(&self) -> String {
+        let (pixels, last_created_at) = sqlx::query_as::<_, (i64, Option<String>)>(
+            "SELECT COUNT(*), CAST(MAX(created_at) AS TEXT) FROM pixel",
+        )
+        .fetch_one(&self.pool)
+        .await
+        .unwrap();
+        let (users, banned) = sqlx::query_as::<_, (i64, Option<i64>)>(
+            r#"SELECT COUNT(*), SUM(CAST(u.is_banned AS INTEGER))
+            FROM discord_user du
+            LEFT JOIN "user" u ON du.user_id = u.user_id"#,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .unwrap();
+        format!(
+            "{pixels}:{}:{users}:{}",
+            last_created_at.unwrap_or_default(),
+            banned.unwrap_or(0),
+        )
+    }
+}
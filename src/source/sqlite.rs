@@ -0,0 +1,242 @@
+use crate::source::{Malformed, Placement, PlacementSource, User};
+use crate::util::color::hex_to_rgba;
+use crate::util::time::{parse_timestamp, TimeWindow};
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::{query, query_scalar, FromRow, Pool, Sqlite, SqlitePool};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+/// The osucord canvas schema, with `pixel`, `discord_user` and `user` tables.
+pub struct SqliteSource {
+    pool: Pool<Sqlite>,
+    /// The database file, `None` for an in-memory database.
+    path: Option<PathBuf>,
+}
+
+/// A `pixel` row joined with its `discord_user`, shared with the Postgres source.
+#[derive(FromRow)]
+pub(super) struct PixelRow {
+    x: i64,
+    y: i64,
+    color: String,
+    created_at: Option<String>,
+    discord_id: Option<String>,
+    mod_action: i64,
+}
+
+impl PixelRow {
+    pub(super) fn placement(self) -> Placement {
+        Placement {
+            x: self.x as i32,
+            y: self.y as i32,
+            color: hex_to_rgba(&self.color),
+            timestamp: parse_created_at(&self.created_at.unwrap()),
+            user: self.discord_id.map_or(0, |id| id.parse().unwrap()),
+            mod_action: self.mod_action == 1,
+        }
+    }
+}
+
+impl SqliteSource {
+    pub async fn connect(url: &str) -> Self {
+        let options = SqliteConnectOptions::from_str(url).unwrap();
+        let path = options.get_filename();
+        SqliteSource {
+            pool: SqlitePool::connect(url).await.unwrap(),
+            path: (path != Path::new(":memory:")).then(|| path.to_path_buf()),
+        }
+    }
+}
+
+impl PlacementSource for SqliteSource {
+    fn placements(&self, window: &TimeWindow) -> BoxStream<'_, Placement> {
+        // The bound arguments have to be owned for the stream to outlive this call,
+        // which the query macros don't allow.
+        sqlx::query_as::<_, PixelRow>(
+            "SELECT p.x, p.y, p.color, CAST(p.created_at AS TEXT) as created_at, du.discord_id, p.mod_action
+            FROM pixel p
+            LEFT JOIN discord_user du ON p.user_id = du.user_id
+            WHERE p.created_at > ? AND p.created_at <= ?
+            ORDER BY p.rowid",
+        )
+        .bind(window.since_sql())
+        .bind(window.until_sql())
+        .fetch(&self.pool)
+        .map(|pixel| pixel.unwrap())
+        .map(PixelRow::placement)
+        .boxed()
+    }
+
+    async fn users(&self) -> HashMap<u64, User> {
+        query!(
+            r#"SELECT du.discord_id, du.discord_username, u.is_banned as "is_banned?"
+            FROM discord_user du
+            LEFT JOIN user u ON du.user_id = u.user_id"#
+        )
+        .fetch_all(&self.pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|u| {
+            let user = User {
+                name: u.discord_username,
+                banned: u.is_banned.unwrap_or(0) != 0,
+            };
+            (u.discord_id.parse().unwrap(), user)
+        })
+        .collect()
+    }
+
+    async fn last_placement(&self) -> Option<i64> {
+        query_scalar!("SELECT CAST(MAX(created_at) AS TEXT) as created_at FROM pixel")
+            .fetch_one(&self.pool)
+            .await
+            .unwrap()
+            .map(|created_at| parse_created_at(&created_at))
+    }
+    async fn colors(&self) -> HashMap<String, u64> {
+        query!("SELECT color, COUNT(*) as count FROM pixel GROUP BY color")
+            .fetch_all(&self.pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| (row.color, row.count as u64))
+            .collect()
+    }
+
+    fn malformed(&self) -> BoxStream<'_, Malformed> {
+        let users = sqlx::query_scalar::<_, String>("SELECT discord_id FROM discord_user")
+            .fetch(&self.pool)
+            .filter_map(|discord_id| std::future::ready(malformed_discord_id(discord_id.unwrap())));
+        let pixels = sqlx::query_as::<_, (i64, Option<String>)>(
+            "SELECT rowid, CAST(created_at AS TEXT) FROM pixel ORDER BY rowid",
+        )
+        .fetch(&self.pool)
+        .filter_map(|row| {
+            let (row, created_at) = row.unwrap();
+            std::future::ready(malformed_created_at(row, created_at))
+        });
+        users.chain(pixels).boxed()
+    }
+
+    async fn fingerprint(&self) -> String {
+        let pixels = query!(
+            "SELECT COUNT(*) as pixels, MAX(rowid) as last_row, MAX(created_at) as last_created_at FROM pixel"
+        )
+        .fetch_one(&self.pool)
+        .await
+        .unwrap();
+        let users = query!(
+            "SELECT COUNT(*) as users, SUM(u.is_banned) as banned
+            FROM discord_user du
+            LEFT JOIN user u ON du.user_id = u.user_id"
+        )
+        .fetch_one(&self.pool)
+        .await
+        .unwrap();
+        // The counts miss rows that were changed in place, the files don't.
+        let files = match &self.path {
+            Some(path) => {
+                let mut wal = path.as_os_str().to_owned();
+                wal.push("-wal");
+                format!(":{}:{}", file_fingerprint(path), file_fingerprint(Path::new(&wal)))
+            }
+            None => String::new(),
+        };
+        format!(
+            "{}:{}:{}:{}:{}{files}",
+            pixels.pixels,
+            pixels.last_row.unwrap_or(0),
+            pixels.last_created_at.unwrap_or_default(),
+            users.users,
+            users.banned.unwrap_or(0),
+        )
+    }
+}
+
+/// The size and modification time of a file, with a missing or empty file being `0`.
+fn file_fingerprint(path: &Path) -> String {
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.len() > 0 => {
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |modified| modified.as_nanos());
+            format!("{}@{modified}", metadata.len())
+        }
+        _ => "0".to_string(),
+    }
+}
+
+pub(super) fn parse_created_at(created_at: &str) -> i64 {
+    parse_timestamp(created_at).unwrap_or_else(|| panic!("invalid created_at '{created_at}'"))
+}
+
+/// What `PixelRow::placement` would panic on in the `created_at` of `row`, shared with the Postgres source.
+pub(super) fn malformed_created_at(row: i64, created_at: Option<String>) -> Option<Malformed> {
+    match created_at {
+        None => Some(Malformed {
+            problem: "placements without a time",
+            example: format!("row {row}"),
+        }),
+        Some(created_at) if parse_timestamp(&created_at).is_none() => Some(Malformed {
+            problem: "placements with a malformed time",
+            example: format!("'{created_at}' in row {row}"),
+        }),
+        Some(_) => None,
+    }
+}
+
+/// Discord ids have to be numbers, as users are told apart by them.
+pub(super) fn malformed_discord_id(discord_id: String) -> Option<Malformed> {
+    discord_id.parse::<u64>().is_err().then(|| Malformed {
+        problem: "users with a malformed discord id",
+        example: format!("'{discord_id}'"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn malformed_finds_rows_without_a_time() {
+        let path = std::env::temp_dir().join(format!("canvas-malformed-{}.db", std::process::id()));
+        let url = format!("sqlite://{}?mode=rwc", path.display());
+        let pool = SqlitePool::connect(&url).await.unwrap();
+        for statement in [
+            "CREATE TABLE discord_user (user_id INTEGER PRIMARY KEY, discord_id TEXT, discord_username TEXT)",
+            "CREATE TABLE pixel (x INTEGER, y INTEGER, color TEXT, user_id INTEGER, created_at TEXT, mod_action INTEGER)",
+            "INSERT INTO discord_user VALUES (1, '123', 'peppy'), (2, 'not a number', 'someone')",
+            "INSERT INTO pixel VALUES (0, 0, '#FFFFFF', 1, '2025-02-28 17:00:00', 0)",
+            "INSERT INTO pixel VALUES (1, 0, '#FFFFFF', 1, NULL, 0)",
+            "INSERT INTO pixel VALUES (2, 0, '#FFFFFF', 1, 'yesterday', 0)",
+        ] {
+            sqlx::query(statement).execute(&pool).await.unwrap();
+        }
+        pool.close().await;
+
+        let source = SqliteSource::connect(&url).await;
+        let malformed: Vec<(&str, String)> = source
+            .malformed()
+            .map(|row| (row.problem, row.example))
+            .collect()
+            .await;
+        source.pool.close().await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            malformed,
+            [
+                ("users with a malformed discord id", "'not a number'".to_string()),
+                ("placements without a time", "row 2".to_string()),
+                ("placements with a malformed time", "'yesterday' in row 3".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+
+use image::RgbaImage;
+use rayon::prelude::*;
+use serde::Deserialize;
+
+use crate::config::Event;
+use crate::source::Placement;
+use crate::util::expansion::Expansion;
+use crate::util::render::{pixel_offset, FrameSize, BLACK};
+use crate::util::time::{parse_time_arg, TimeArg};
+
+/// Where the camera looks at some point of the event. `x` and `y` are the canvas coordinates in
+/// the middle of the frame, and at `zoom` 2 it shows half as many pixels across as at 1.
+#[derive(Clone, Debug)]
+pub struct CameraKeyframe {
+    pub time: i64,
+    pub x: f64,
+    pub y: f64,
+    pub zoom: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraFile {
+    keyframes: Vec<CameraEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraEntry {
+    at: String,
+    x: f64,
+    y: f64,
+    #[serde(default = "default_zoom")]
+    zoom: f64,
+}
+
+fn default_zoom() -> f64 {
+    1.0
+}
+
+/// The size of the cells placements are counted in to find where the most is going on.
+const FOLLOW_CELL: i32 = 16;
+/// The follow camera never shows less than this many pixels across.
+const FOLLOW_MIN_EXTENT: f64 = 48.0;
+/// How much of the way to where it wants to be the follow camera moves every frame.
+const FOLLOW_SMOOTHING: f64 = 0.08;
+
+/// Picks the part of the canvas each frame shows.
+#[derive(Clone, Debug)]
+pub enum Camera {
+    /// Eases from one keyframe to the next.
+    Path(Vec<CameraKeyframe>),
+    /// Frames wherever most was placed lately.
+    Follow(Follow),
+}
+
+#[derive(Clone, Debug)]
+pub struct Follow {
+    window: i64,
+    max_zoom: f64,
+    recent: VecDeque<(i64, i32, i32)>,
+    current: Option<CameraKeyframe>,
+}
+
+impl Follow {
+    fn placed(&mut self, placement: &Placement) {
+        self.recent.push_back((placement.timestamp, placement.x, placement.y));
+    }
+
+    /// Moves a bit towards the busiest area of the last `window` seconds, zoomed to fit the
+    /// placements around it. Without any, it drifts back out to the whole canvas.
+    fn at(&mut self, time: i64, expansion: &Expansion, area: (u32, u32)) -> CameraKeyframe {
+        while self.recent.front().is_some_and(|(placed, _, _)| *placed <= time - self.window) {
+            self.recent.pop_front();
+        }
+
+        let whole = CameraKeyframe {
+            time,
+            x: expansion.x as f64 + expansion.width as f64 / 2.0,
+            y: expansion.y as f64 + expansion.height as f64 / 2.0,
+            zoom: 1.0,
+        };
+        let target = self.busiest(time, expansion, area).unwrap_or(whole.clone());
+        let current = self.current.get_or_insert(whole);
+        current.time = time;
+        current.x += (target.x - current.x) * FOLLOW_SMOOTHING;
+        current.y += (target.y - current.y) * FOLLOW_SMOOTHING;
+        current.zoom *= (target.zoom / current.zoom).powf(FOLLOW_SMOOTHING);
+        current.clone()
+    }
+
+    fn busiest(&self, time: i64, expansion: &Expansion, area: (u32, u32)) -> Option<CameraKeyframe> {
+        let mut cells: HashMap<(i32, i32), u32> = HashMap::new();
+        for (_, x, y) in &self.recent {
+            *cells.entry((x.div_euclid(FOLLOW_CELL), y.div_euclid(FOLLOW_CELL))).or_default() += 1;
+        }
+        // The densest block of 3x3 cells, so a single busy cell doesn't win over a busy area.
+        let density = |(x, y): (i32, i32)| -> u32 {
+            (-1..=1)
+                .flat_map(|dx| (-1..=1).map(move |dy| (x + dx, y + dy)))
+                .map(|cell| cells.get(&cell).copied().unwrap_or(0))
+                .sum()
+        };
+        let (focus_x, focus_y) = cells.keys().copied().max_by_key(|&cell| (density(cell), cell))?;
+        let focus = (
+            (focus_x * FOLLOW_CELL + FOLLOW_CELL / 2) as f64,
+            (focus_y * FOLLOW_CELL + FOLLOW_CELL / 2) as f64,
+        );
+
+        // The placements around the focus, leaving out whatever happens on the other side of the canvas.
+        let radius = expansion.width.max(expansion.height) as f64 / 4.0;
+        let nearby = self
+            .recent
+            .iter()
+            .map(|(_, x, y)| (*x as f64 + 0.5, *y as f64 + 0.5))
+            .filter(|(x, y)| (x - focus.0).abs() <= radius && (y - focus.1).abs() <= radius)
+            .collect::<Vec<_>>();
+        let count = nearby.len() as f64;
+        let (x, y) = nearby.iter().fold((0.0, 0.0), |(sum_x, sum_y), (x, y)| (sum_x + x, sum_y + y));
+        let (x, y) = (x / count, y / count);
+        let (spread_x, spread_y) = nearby.iter().fold((0.0, 0.0), |(sum_x, sum_y), (nearby_x, nearby_y)| {
+            (sum_x + (nearby_x - x).powi(2), sum_y + (nearby_y - y).powi(2))
+        });
+        let (spread_x, spread_y) = ((spread_x / count).sqrt(), (spread_y / count).sqrt());
+
+        // Two standard deviations to each side covers most of it.
+        let (width, height) = (
+            (4.0 * spread_x).max(FOLLOW_MIN_EXTENT),
+            (4.0 * spread_y).max(FOLLOW_MIN_EXTENT),
+        );
+        let zoom = (area.0 as f64 / width).min(area.1 as f64 / height);
+        Some(CameraKeyframe {
+            time,
+            x,
+            y,
+            zoom: zoom.clamp(1.0, self.max_zoom),
+        })
+    }
+}
+
+impl Camera {
+    /// Always looks at the same spot.
+    pub fn fixed(x: f64, y: f64) -> Self {
+        Camera::Path(vec![CameraKeyframe {
+            time: 0,
+            x,
+            y,
+            zoom: 1.0,
+        }])
+    }
+
+    /// Follows the placements of the last `minutes`, zooming in at most `max_zoom` times.
+    pub fn follow(minutes: i64, max_zoom: f64) -> Self {
+        Camera::Follow(Follow {
+            window: minutes * 60,
+            max_zoom,
+            recent: VecDeque::new(),
+            current: None,
+        })
+    }
+
+    /// Reads a camera path, with the keyframe times like `--since`, counting back from `end`.
+    pub fn load(path: &Path, event: &Event, end: i64) -> Self {
+        let text = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Failed to read {}: {e}", path.display()));
+        let file: CameraFile =
+            toml::from_str(&text).unwrap_or_else(|e| panic!("Invalid {}: {e}", path.display()));
+        assert!(!file.keyframes.is_empty(), "{} has no keyframes", path.display());
+
+        let mut keyframes = file
+            .keyframes
+            .into_iter()
+            .map(|entry| {
+                let time = match parse_time_arg(&entry.at) {
+                    Ok(TimeArg::Absolute(timestamp)) => timestamp,
+                    Ok(TimeArg::FromStart(seconds)) => event.start + seconds,
+                    Ok(TimeArg::FromEnd(seconds)) => end - seconds,
+                    Err(e) => panic!("invalid camera keyframe time '{}': {e}", entry.at),
+                };
+                assert!(entry.zoom > 0.0, "camera zoom has to be above 0, not {}", entry.zoom);
+                CameraKeyframe {
+                    time,
+                    x: entry.x,
+                    y: entry.y,
+                    zoom: entry.zoom,
+                }
+            })
+            .collect::<Vec<_>>();
+        keyframes.sort_by_key(|keyframe| keyframe.time);
+        Camera::Path(keyframes)
+    }
+
+    pub fn placed(&mut self, placement: &Placement) {
+        if let Camera::Follow(follow) = self {
+            follow.placed(placement);
+        }
+    }
+
+    fn at(keyframes: &[CameraKeyframe], time: i64) -> CameraKeyframe {
+        let next = keyframes.iter().position(|keyframe| keyframe.time > time);
+        let (from, to) = match next {
+            Some(0) => return keyframes[0].clone(),
+            None => return keyframes.last().unwrap().clone(),
+            Some(next) => (&keyframes[next - 1], &keyframes[next]),
+        };
+        let t = (time - from.time) as f64 / (to.time - from.time) as f64;
+        let t = t * t * (3.0 - 2.0 * t);
+        CameraKeyframe {
+            time,
+            x: from.x + (to.x - from.x) * t,
+            y: from.y + (to.y - from.y) * t,
+            // Zooming by the same factor every frame looks steady, zooming by the same amount doesn't.
+            zoom: from.zoom * (to.zoom / from.zoom).powf(t),
+        }
+    }
+
+    /// Takes what the camera sees at `time` from `image`, which shows `expansion` laid out on
+    /// `canvas`, and fills `view` next to the sidebar with it.
+    pub fn view(
+        &mut self,
+        image: &RgbaImage,
+        canvas: &FrameSize,
+        view: &mut RgbaImage,
+        frame: &FrameSize,
+        expansion: &Expansion,
+        time: i64,
+    ) {
+        let (area_width, area_height) = (frame.width - frame.sidebar, frame.height);
+        let camera = match self {
+            Camera::Path(keyframes) => Camera::at(keyframes, time),
+            Camera::Follow(follow) => follow.at(time, expansion, (area_width, area_height)),
+        };
+        let (width, height) = (area_width as f64 / camera.zoom, area_height as f64 / camera.zoom);
+        let (left, top) = (camera.x - width / 2.0, camera.y - height / 2.0);
+        let (x_offset, y_offset) = pixel_offset(canvas, expansion);
+
+        // The column of the image every column of the frame shows, the same on every row.
+        let columns = (0..area_width)
+            .map(|frame_x| {
+                let x = (left + (frame_x as f64 + 0.5) * width / area_width as f64).floor() as i64;
+                let image_x = x - expansion.x as i64 + x_offset as i64;
+                (0..image.width() as i64).contains(&image_x).then_some(image_x as u32)
+            })
+            .collect::<Vec<_>>();
+        view.par_chunks_exact_mut(frame.width as usize * 4)
+            .enumerate()
+            .for_each(|(frame_y, line)| {
+                let y = (top + (frame_y as f64 + 0.5) * height / area_height as f64).floor() as i64;
+                let image_y = y - expansion.y as i64 + y_offset as i64;
+                let row = (0..image.height() as i64).contains(&image_y).then_some(image_y as u32);
+                for (frame_x, pixel) in line.chunks_exact_mut(4).enumerate() {
+                    let colour = match (columns.get(frame_x), row) {
+                        (Some(Some(image_x)), Some(image_y)) => *image.get_pixel(*image_x, image_y),
+                        _ => BLACK,
+                    };
+                    pixel.copy_from_slice(&colour.0);
+                }
+            });
+    }
+}
+
+/// Parses a `--region`, the corners `x0,y0,x1,y1` of the part of the canvas to show, both included.
+pub fn parse_region(text: &str) -> Result<Expansion, String> {
+    let corners = text
+        .split(',')
+        .map(|value| value.trim().parse::<i32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("invalid region '{text}': {e}"))?;
+    let [x0, y0, x1, y1] = corners[..] else {
+        return Err(format!("invalid region '{text}', use x0,y0,x1,y1"));
+    };
+    Ok(Expansion {
+        x: x0.min(x1),
+        y: y0.min(y1),
+        width: x0.abs_diff(x1) + 1,
+        height: y0.abs_diff(y1) + 1,
+        at: 0,
+    })
+}
@@ -1,18 +1,16 @@
 use image::{ImageBuffer, Rgba};
-use crate::config::CANVAS_SIZES;
+use crate::util::expansion::Expansion;
 
-const FINAL_CANVAS_SIZE: (u32, u32) = CANVAS_SIZES[CANVAS_SIZES.len() - 1];
-
-pub fn white_image() -> ImageBuffer<Rgba<u8>, Vec<u8>> {
-    single_color_image(Rgba([255, 255, 255, 255]))
+pub fn white_image(bounds: &Expansion) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    single_color_image(bounds, Rgba([255, 255, 255, 255]))
 }
 
-pub fn blank_image() -> ImageBuffer<Rgba<u8>, Vec<u8>> {
-    single_color_image(Rgba([0, 0, 0, 0]))
+pub fn blank_image(bounds: &Expansion) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    single_color_image(bounds, Rgba([0, 0, 0, 0]))
 }
 
-fn single_color_image(color: Rgba<u8>) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
-    let mut image = image::ImageBuffer::new(FINAL_CANVAS_SIZE.0, FINAL_CANVAS_SIZE.1);
+fn single_color_image(bounds: &Expansion, color: Rgba<u8>) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let mut image = image::ImageBuffer::new(bounds.width, bounds.height);
     for x in 0..image.width() {
         for y in 0..image.height() {
             image.put_pixel(x, y, color);
@@ -20,4 +18,4 @@ fn single_color_image(color: Rgba<u8>) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
     }
 
     image
-}
\ No newline at end of file
+}
@@ -1,8 +1,57 @@
+use hsv::hsv_to_rgb;
 use image::Rgba;
+use std::collections::HashMap;
+
+const USER_COLORS: &str = "db/user_colors.json";
 
 pub fn hex_to_rgba(hex: &str) -> Rgba<u8> {
-    let r = u8::from_str_radix(&hex[1..3], 16).unwrap();
-    let g = u8::from_str_radix(&hex[3..5], 16).unwrap();
-    let b = u8::from_str_radix(&hex[5..7], 16).unwrap();
-    Rgba([r, g, b, 255])
-}
\ No newline at end of file
+    parse_hex(hex).unwrap_or_else(|| panic!("invalid colour '{hex}'"))
+}
+
+/// Parses a `#RRGGBB` colour, or returns `None` if it is malformed.
+pub fn parse_hex(hex: &str) -> Option<Rgba<u8>> {
+    if hex.len() != 7 || !hex.starts_with('#') {
+        return None;
+    }
+    let r = u8::from_str_radix(hex.get(1..3)?, 16).ok()?;
+    let g = u8::from_str_radix(hex.get(3..5)?, 16).ok()?;
+    let b = u8::from_str_radix(hex.get(5..7)?, 16).ok()?;
+    Some(Rgba([r, g, b, 255]))
+}
+
+pub fn rgba_to_hex(color: Rgba<u8>) -> String {
+    format!("#{:02X}{:02X}{:02X}", color[0], color[1], color[2])
+}
+
+/// The colour each user gets on the usermap, kept in `db/user_colors.json` so they stay the same
+/// between renders. Users without one get a colour made from their discord id.
+pub struct UserColors {
+    colors: HashMap<u64, String>,
+}
+
+impl UserColors {
+    pub fn load() -> Self {
+        let colors = match std::fs::read_to_string(USER_COLORS) {
+            Ok(json) => serde_json::from_str::<HashMap<u64, String>>(&json).unwrap(),
+            Err(_) => HashMap::new(),
+        };
+        UserColors { colors }
+    }
+
+    pub fn get(&mut self, discord_id: u64) -> Rgba<u8> {
+        if let Some(color) = self.colors.get(&discord_id) {
+            return hex_to_rgba(color);
+        }
+        let h = discord_id as f64 % 360.0;
+        let s = ((discord_id as f64 % 40.0) + 60.0) / 100.0;
+        let v = ((discord_id as f64 % 50.0) + 50.0) / 100.0;
+        let (r, g, b) = hsv_to_rgb(h, s, v);
+        self.colors.insert(discord_id, format!("#{r:02x}{g:02x}{b:02x}"));
+        Rgba([r, g, b, 255])
+    }
+
+    pub fn save(&self) {
+        let json = serde_json::to_string(&self.colors).unwrap();
+        std::fs::write(USER_COLORS, json).unwrap();
+    }
+}
@@ -0,0 +1,452 @@
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use aformat::aformat;
+use clap::ValueEnum;
+use flate2::Crc;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, Rgba, RgbaImage};
+use rayon::prelude::*;
+use serde::Deserialize;
+use to_arraystring::ToArrayString;
+
+use crate::util::camera::Camera;
+use crate::util::render::FrameSize;
+
+/// What the video renderers write their frames to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoFormat {
+    /// H.264 through ffmpeg.
+    #[default]
+    Mp4,
+    /// VP9 through ffmpeg.
+    Webm,
+    Gif,
+    Apng,
+    /// A numbered PNG file per frame.
+    Png,
+    /// Uncompressed YUV 4:4:4 to stdout, for piping into other tools.
+    Y4m,
+    /// RGBA frames to stdout.
+    Raw,
+}
+
+#[derive(Clone, Debug)]
+pub struct Encoding {
+    pub format: VideoFormat,
+    /// The ffmpeg CRF for mp4 and webm, lower is better. Defaults to 24 for mp4 and 32 for webm.
+    pub quality: Option<u8>,
+    /// The x264 preset for mp4.
+    pub preset: String,
+    /// The frame the video renderers draw the canvas on, which is `frame` unless there is a camera.
+    pub canvas: FrameSize,
+    /// The frame that goes into the video.
+    pub frame: FrameSize,
+    pub camera: Option<Camera>,
+    /// Draws the HUD over the frames when set, counting users active in the last this many minutes.
+    pub hud: Option<i64>,
+}
+
+/// How many frames can wait for the encoder before drawing the next one waits instead.
+const FRAME_QUEUE: usize = 8;
+
+/// Takes the frames of a video one by one.
+pub trait FrameEncoder: Send {
+    /// Gives back a frame it is done with, which may not be the one it was given.
+    fn write_frame(&mut self, frame: RgbaImage) -> Option<RgbaImage>;
+
+    /// Writes whatever is still buffered and waits for the output to be complete.
+    fn finish(self: Box<Self>);
+}
+
+/// Encodes on a thread of its own, so the next frames are drawn while the last ones are encoded.
+/// The frames go back once they are written, to be drawn on again instead of allocating new ones.
+pub struct EncoderThread {
+    frames: SyncSender<RgbaImage>,
+    spare: Receiver<RgbaImage>,
+    thread: JoinHandle<()>,
+}
+
+impl EncoderThread {
+    pub fn start(encoding: &Encoding, fps: u8, output: &Path, title: &str) -> Self {
+        let mut encoder = start_encoder(encoding, fps, output, title);
+        let (frames, queue) = sync_channel::<RgbaImage>(FRAME_QUEUE);
+        let (written, spare) = sync_channel(FRAME_QUEUE + 2);
+        let thread = thread::spawn(move || {
+            for frame in queue {
+                // Only as many as can be in flight, any more would never be used.
+                if let Some(frame) = encoder.write_frame(frame) {
+                    let _ = written.try_send(frame);
+                }
+            }
+            encoder.finish();
+        });
+        EncoderThread { frames, spare, thread }
+    }
+
+    /// A frame to draw on, with whatever was on it before.
+    pub fn buffer(&self, width: u32, height: u32) -> RgbaImage {
+        match self.spare.try_recv() {
+            Ok(frame) if frame.dimensions() == (width, height) => frame,
+            _ => RgbaImage::new(width, height),
+        }
+    }
+
+    pub fn write_frame(&self, frame: RgbaImage) {
+        // It only hangs up when it panicked, which already said why.
+        self.frames.send(frame).expect("The encoder stopped");
+    }
+
+    /// Waits for the queued frames to be encoded.
+    pub fn finish(self) {
+        drop(self.frames);
+        if let Err(panic) = self.thread.join() {
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+/// Starts encoding a video at `output`, which gets the extension of the format.
+/// Only mp4 and webm need ffmpeg, the others are encoded here.
+fn start_encoder(encoding: &Encoding, fps: u8, output: &Path, title: &str) -> Box<dyn FrameEncoder> {
+    match encoding.format {
+        VideoFormat::Mp4 | VideoFormat::Webm => Box::new(FfmpegEncoder::start(encoding, fps, output, title)),
+        VideoFormat::Gif => {
+            if fps > 50 {
+                eprintln!("GIFs play at most 50 frames per second, lower --fps to keep the speed");
+            }
+            let file = create(&output.with_extension("gif"));
+            let mut encoder = GifEncoder::new_with_speed(file, 10);
+            encoder.set_repeat(Repeat::Infinite).unwrap();
+            Box::new(GifOutput {
+                encoder,
+                fps,
+                size: encoding.frame,
+                last: None,
+            })
+        }
+        VideoFormat::Apng => {
+            let path = output.with_extension("png");
+            let (width, height) = encoding.frame.output;
+            let mut encoder = png::Encoder::new(create(&path), width, height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_animated(u32::MAX, 0).unwrap();
+            Box::new(ApngOutput {
+                path,
+                writer: encoder.write_header().unwrap(),
+                fps,
+                size: encoding.frame,
+                frames: 0,
+                last: None,
+            })
+        }
+        VideoFormat::Png => {
+            create_dir_all(output).expect("Failed to create frame directory");
+            Box::new(PngSequence {
+                directory: output.to_path_buf(),
+                size: encoding.frame,
+                count: 0,
+            })
+        }
+        VideoFormat::Y4m => {
+            let mut stdout = BufWriter::new(io::stdout());
+            let (width, height) = encoding.frame.output;
+            writeln!(stdout, "YUV4MPEG2 W{width} H{height} F{fps}:1 Ip A1:1 C444").unwrap();
+            Box::new(Y4mOutput {
+                stdout,
+                size: encoding.frame,
+            })
+        }
+        VideoFormat::Raw => Box::new(RawOutput {
+            stdout: BufWriter::new(io::stdout()),
+            size: encoding.frame,
+        }),
+    }
+}
+
+struct FfmpegEncoder {
+    child: Child,
+    stdin: ChildStdin,
+}
+
+impl FfmpegEncoder {
+    fn start(encoding: &Encoding, fps: u8, output: &Path, title: &str) -> Self {
+        let (extension, codec): (&str, &[&str]) = match encoding.format {
+            VideoFormat::Webm => ("webm", &["-c:v", "libvpx-vp9", "-b:v", "0", "-row-mt", "1"]),
+            _ => ("mp4", &["-c:v", "libx264", "-preset", &encoding.preset, "-tune", "animation", "-keyint_min", "64"]),
+        };
+        let quality = encoding
+            .quality
+            .unwrap_or(if encoding.format == VideoFormat::Webm { 32 } else { 24 });
+        let output = output.with_extension(extension);
+        let frame = encoding.frame;
+        let (width, height) = frame.output;
+        let (scaled_width, scaled_height) = frame.scaled();
+        let flags = if frame.shrink > 1 { "area" } else { "neighbor" };
+        let scale = format!("scale={scaled_width}:{scaled_height}:flags={flags}");
+        // Black bars when the scaled frame doesn't fill the resolution exactly.
+        let filter = if frame.scaled() == frame.output {
+            scale
+        } else {
+            format!("{scale},pad={width}:{height}:(ow-iw)/2:(oh-ih)/2")
+        };
+
+        #[rustfmt::skip]
+        let mut child = Command::new("ffmpeg")
+            .args([
+                "-framerate", &fps.to_arraystring(),
+                "-f", "rawvideo",
+                "-pix_fmt", "rgba",
+                "-video_size", &aformat!("{}x{}", frame.width, frame.height),
+                "-i", "pipe:0",
+                "-pix_fmt", "yuv420p",
+                "-y",
+                "-vf", &filter,
+                "-crf", &quality.to_arraystring(),
+                "-metadata", &format!("title={title}"),
+            ])
+            .args(codec)
+            .arg(output.to_str().expect("Output path must be valid UTF-8"))
+            .stdin(Stdio::piped())
+            .spawn()
+            .unwrap_or_else(|e| {
+                panic!("Failed to start ffmpeg: {e}. Install it, or use --format gif, apng or png")
+            });
+
+        let stdin = child.stdin.take().expect("Failed to open stdin");
+        FfmpegEncoder { child, stdin }
+    }
+}
+
+impl FrameEncoder for FfmpegEncoder {
+    fn write_frame(&mut self, frame: RgbaImage) -> Option<RgbaImage> {
+        self.stdin
+            .write_all(frame.as_raw())
+            .expect("Failed to write frame");
+        Some(frame)
+    }
+
+    fn finish(self: Box<Self>) {
+        drop(self.stdin);
+        let output = self.child.wait_with_output().expect("Failed to wait on child");
+        if !output.status.success() {
+            panic!("ffmpeg failed ({}), see its output above", output.status);
+        }
+    }
+}
+
+struct GifOutput {
+    encoder: GifEncoder<BufWriter<File>>,
+    fps: u8,
+    size: FrameSize,
+    /// The last frame and how many times in a row it was written, to show it that much longer.
+    last: Option<(RgbaImage, u32)>,
+}
+
+impl GifOutput {
+    /// Encodes the last frame, and gives it back.
+    fn flush(&mut self) -> Option<RgbaImage> {
+        let (image, repeats) = self.last.take()?;
+        let delay = Delay::from_numer_denom_ms(repeats * 1000, self.fps as u32);
+        self.encoder
+            .encode_frame(Frame::from_parts(scaled(&image, &self.size), 0, 0, delay))
+            .expect("Failed to write frame");
+        Some(image)
+    }
+}
+
+impl FrameEncoder for GifOutput {
+    fn write_frame(&mut self, frame: RgbaImage) -> Option<RgbaImage> {
+        if let Some((last, repeats)) = &mut self.last {
+            if last.as_raw() == frame.as_raw() {
+                *repeats += 1;
+                return Some(frame);
+            }
+        }
+        let last = self.flush();
+        self.last = Some((frame, 1));
+        last
+    }
+
+    fn finish(mut self: Box<Self>) {
+        self.flush();
+    }
+}
+
+/// APNG wants the amount of frames up front, so the header says as many as it can hold
+/// and the real amount is patched in at the end.
+struct ApngOutput {
+    path: PathBuf,
+    writer: png::Writer<BufWriter<File>>,
+    fps: u8,
+    size: FrameSize,
+    frames: u32,
+    /// The last frame and how many times in a row it was written, to show it that much longer.
+    last: Option<(RgbaImage, u16)>,
+}
+
+impl ApngOutput {
+    /// Encodes the last frame, and gives it back.
+    fn flush(&mut self) -> Option<RgbaImage> {
+        let (image, repeats) = self.last.take()?;
+        self.writer.set_frame_delay(repeats, self.fps as u16).unwrap();
+        self.writer
+            .write_image_data(scaled(&image, &self.size).as_raw())
+            .expect("Failed to write frame");
+        self.frames += 1;
+        Some(image)
+    }
+}
+
+impl FrameEncoder for ApngOutput {
+    fn write_frame(&mut self, frame: RgbaImage) -> Option<RgbaImage> {
+        if let Some((last, repeats)) = &mut self.last {
+            if last.as_raw() == frame.as_raw() && *repeats < u16::MAX {
+                *repeats += 1;
+                return Some(frame);
+            }
+        }
+        let last = self.flush();
+        self.last = Some((frame, 1));
+        last
+    }
+
+    fn finish(mut self: Box<Self>) {
+        self.flush();
+        let ApngOutput { path, writer, frames, .. } = *self;
+        writer.finish().expect("Failed to write frame");
+
+        // acTL comes right after the signature and IHDR, which are 8 and 25 bytes.
+        let mut actl = [0; 8];
+        actl[..4].copy_from_slice(&frames.to_be_bytes());
+        let mut crc = Crc::new();
+        crc.update(b"acTL");
+        crc.update(&actl);
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(8 + 25 + 8)).unwrap();
+        file.write_all(&actl).unwrap();
+        file.write_all(&crc.sum().to_be_bytes()).unwrap();
+    }
+}
+
+struct PngSequence {
+    directory: PathBuf,
+    size: FrameSize,
+    count: u32,
+}
+
+impl FrameEncoder for PngSequence {
+    fn write_frame(&mut self, frame: RgbaImage) -> Option<RgbaImage> {
+        self.count += 1;
+        scaled(&frame, &self.size)
+            .save(self.directory.join(format!("{:06}.png", self.count)))
+            .expect("Failed to write frame");
+        Some(frame)
+    }
+
+    fn finish(self: Box<Self>) {}
+}
+
+struct Y4mOutput {
+    stdout: BufWriter<io::Stdout>,
+    size: FrameSize,
+}
+
+impl FrameEncoder for Y4mOutput {
+    // BT.601 with limited range, which is what players assume for Y4M.
+    fn write_frame(&mut self, frame: RgbaImage) -> Option<RgbaImage> {
+        let scaled = scaled(&frame, &self.size);
+        let pixels = scaled.as_raw().len() / 4;
+        let mut planes = vec![0; pixels * 3];
+        let (y, chroma) = planes.split_at_mut(pixels);
+        let (u, v) = chroma.split_at_mut(pixels);
+        scaled
+            .as_raw()
+            .par_chunks_exact(4)
+            .zip(y.par_iter_mut().zip(u.par_iter_mut().zip(v.par_iter_mut())))
+            .for_each(|(pixel, (y, (u, v)))| {
+                let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(|channel| channel as f32);
+                *y = (16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0).round() as u8;
+                *u = (128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0).round() as u8;
+                *v = (128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0).round() as u8;
+            });
+        self.stdout.write_all(b"FRAME\n").unwrap();
+        self.stdout.write_all(&planes).unwrap();
+        Some(frame)
+    }
+
+    fn finish(mut self: Box<Self>) {
+        self.stdout.flush().unwrap();
+    }
+}
+
+struct RawOutput {
+    stdout: BufWriter<io::Stdout>,
+    size: FrameSize,
+}
+
+impl FrameEncoder for RawOutput {
+    fn write_frame(&mut self, frame: RgbaImage) -> Option<RgbaImage> {
+        self.stdout.write_all(scaled(&frame, &self.size).as_raw()).unwrap();
+        Some(frame)
+    }
+
+    fn finish(mut self: Box<Self>) {
+        self.stdout.flush().unwrap();
+    }
+}
+
+// What ffmpeg does with its filters: blow up the frame, centre it on black, and drop the alpha channel
+// like the video codecs do.
+fn scaled(frame: &RgbaImage, size: &FrameSize) -> RgbaImage {
+    let (width, height) = size.output;
+    let (scaled_width, scaled_height) = size.scaled();
+    let x_offset = (width - scaled_width) / 2;
+    let y_offset = (height - scaled_height) / 2;
+    let mut image = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255]));
+    image
+        .par_chunks_exact_mut(width as usize * 4)
+        .skip(y_offset as usize)
+        .take(scaled_height as usize)
+        .enumerate()
+        .for_each(|(row, line)| {
+            if size.shrink > 1 {
+                // The average of the block of frame pixels, like ffmpeg's area scaling.
+                let shrink = size.shrink;
+                let top = row as u32 * shrink;
+                for x in 0..scaled_width {
+                    let mut sum = [0; 3];
+                    for y in top..top + shrink {
+                        for x in x * shrink..(x + 1) * shrink {
+                            let pixel = frame.get_pixel(x, y);
+                            sum.iter_mut().zip(pixel.0).for_each(|(sum, channel)| *sum += channel as u32);
+                        }
+                    }
+                    let [r, g, b] = sum.map(|sum| (sum / (shrink * shrink)) as u8);
+                    let left = ((x_offset + x) * 4) as usize;
+                    line[left..left + 4].copy_from_slice(&[r, g, b, 255]);
+                }
+                return;
+            }
+            let y = row as u32 / size.scale;
+            for x in 0..frame.width() {
+                let [r, g, b, _] = frame.get_pixel(x, y).0;
+                let left = ((x_offset + x * size.scale) * 4) as usize;
+                for pixel in line[left..left + size.scale as usize * 4].chunks_exact_mut(4) {
+                    pixel.copy_from_slice(&[r, g, b, 255]);
+                }
+            }
+        });
+    image
+}
+
+fn create(path: &Path) -> BufWriter<File> {
+    let file = File::create(path).unwrap_or_else(|e| panic!("Failed to create {}: {e}", path.display()));
+    BufWriter::new(file)
+}
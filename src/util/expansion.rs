@@ -0,0 +1,191 @@
+use crate::source::PlacementSource;
+use crate::util::time::{parse_timestamp, TimeWindow};
+use futures_util::StreamExt;
+use serde::Deserialize;
+
+// How long after the first placement outside the canvas we keep collecting
+// placements into the same inferred expansion.
+const INFER_WINDOW: i64 = 10 * 60;
+
+/// One step of the canvas growing. `x` and `y` are the placement coordinates
+/// of the top left pixel, so expansions towards the left or top have a
+/// negative origin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Expansion {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub at: i64,
+}
+
+impl Expansion {
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x
+            && y >= self.y
+            && x < self.x + self.width as i32
+            && y < self.y + self.height as i32
+    }
+
+    /// Converts placement coordinates into coordinates relative to the top left of this expansion.
+    pub fn local(&self, x: i32, y: i32) -> (u32, u32) {
+        ((x - self.x) as u32, (y - self.y) as u32)
+    }
+
+    fn union(&self, x: i32, y: i32) -> Expansion {
+        let left = self.x.min(x);
+        let top = self.y.min(y);
+        let right = (self.x + self.width as i32).max(x + 1);
+        let bottom = (self.y + self.height as i32).max(y + 1);
+        Expansion {
+            x: left,
+            y: top,
+            width: (right - left) as u32,
+            height: (bottom - top) as u32,
+            at: self.at,
+        }
+    }
+}
+
+/// An `[[expansions]]` entry in the event config.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExpansionEntry {
+    pub at: String,
+    #[serde(default)]
+    pub x: i32,
+    #[serde(default)]
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct ExpansionSchedule {
+    expansions: Vec<Expansion>,
+}
+
+impl ExpansionSchedule {
+    /// Uses the expansions from the event config, or infers them from the placements if there are none.
+    pub async fn load(
+        source: &impl PlacementSource,
+        entries: &[ExpansionEntry],
+        event_start: i64,
+    ) -> Self {
+        if entries.is_empty() {
+            eprintln!("No expansions configured, inferring canvas expansions from placements");
+            return Self::infer(source, event_start).await;
+        }
+        Self::configured(entries)
+    }
+
+    pub fn configured(entries: &[ExpansionEntry]) -> Self {
+        let expansions: Vec<Expansion> = entries
+            .iter()
+            .map(|entry| Expansion {
+                x: entry.x,
+                y: entry.y,
+                width: entry.width,
+                height: entry.height,
+                at: parse_timestamp(&entry.at)
+                    .unwrap_or_else(|| panic!("invalid expansion timestamp '{}'", entry.at)),
+            })
+            .collect();
+
+        ExpansionSchedule::new(expansions)
+    }
+
+    pub fn new(mut expansions: Vec<Expansion>) -> Self {
+        assert!(!expansions.is_empty(), "the canvas needs at least one expansion");
+        expansions.sort_by_key(|expansion| expansion.at);
+        ExpansionSchedule { expansions }
+    }
+
+    async fn infer(source: &impl PlacementSource, event_start: i64) -> Self {
+        let window = TimeWindow {
+            since: event_start,
+            until: None,
+        };
+        let mut placements = source.placements(&window);
+
+        let mut expansions: Vec<Expansion> = vec![];
+        let mut pending: Option<Expansion> = None;
+        while let Some(pixel) = placements.next().await {
+            let (x, y, timestamp) = (pixel.x, pixel.y, pixel.timestamp);
+
+            if let Some(expansion) = pending {
+                if timestamp - expansion.at > INFER_WINDOW {
+                    expansions.push(expansion);
+                    pending = None;
+                }
+            }
+
+            let current = pending.or(expansions.last().copied());
+            pending = match current {
+                Some(current) if current.contains(x, y) => pending,
+                Some(current) if pending.is_some() => Some(current.union(x, y)),
+                Some(current) => Some(Expansion {
+                    at: timestamp,
+                    ..current.union(x, y)
+                }),
+                None => Some(Expansion {
+                    x,
+                    y,
+                    width: 1,
+                    height: 1,
+                    at: timestamp,
+                }),
+            };
+        }
+        expansions.extend(pending);
+        assert!(!expansions.is_empty(), "no placements to infer the canvas size from");
+
+        ExpansionSchedule { expansions }
+    }
+
+    pub fn get(&self, idx: usize) -> &Expansion {
+        &self.expansions[idx]
+    }
+
+    /// The widest and the tallest the canvas gets, which every video frame has to fit.
+    pub fn largest(&self) -> (u32, u32) {
+        let width = self.expansions.iter().map(|expansion| expansion.width).max().unwrap();
+        let height = self.expansions.iter().map(|expansion| expansion.height).max().unwrap();
+        (width, height)
+    }
+
+    /// The area covering every expansion, used for canvas wide grids and images.
+    pub fn bounds(&self) -> Expansion {
+        self.expansions[1..]
+            .iter()
+            .fold(self.expansions[0], |bounds, expansion| {
+                bounds
+                    .union(expansion.x, expansion.y)
+                    .union(
+                        expansion.x + expansion.width as i32 - 1,
+                        expansion.y + expansion.height as i32 - 1,
+                    )
+            })
+    }
+
+    /// The canvas as scheduled at `timestamp`, ignoring where placements actually went.
+    pub fn at(&self, timestamp: i64) -> &Expansion {
+        self.expansions
+            .iter()
+            .rev()
+            .find(|expansion| expansion.at <= timestamp)
+            .unwrap_or(&self.expansions[0])
+    }
+
+    /// Finds which expansion a placement belongs to, never going back from `current`.
+    /// Placements outside the scheduled canvas move on to the next expansion early.
+    pub fn index_for(&self, current: usize, timestamp: i64, x: i32, y: i32) -> usize {
+        let mut idx = current;
+        while idx + 1 < self.expansions.len()
+            && (self.expansions[idx + 1].at <= timestamp || !self.expansions[idx].contains(x, y))
+        {
+            idx += 1;
+        }
+        idx
+    }
+}
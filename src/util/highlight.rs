@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+
+use clap::ValueEnum;
+use image::{Rgba, RgbaImage};
+use serde::Deserialize;
+
+use crate::source::Placement;
+use crate::util::expansion::Expansion;
+use crate::util::render::{frame_position, FrameSize};
+
+/// How far the glow reaches around a pixel, in canvas pixels.
+const GLOW_RADIUS: i64 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum HighlightStyle {
+    /// The pixel itself lights up.
+    Flash,
+    /// A ring around the pixel lights up, leaving its colour alone.
+    Outline,
+    /// The pixel and the ones around it light up, less so further away.
+    Glow,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Highlight {
+    pub style: HighlightStyle,
+    /// How many frames it takes to fade out.
+    pub frames: u32,
+    /// The colour for placements, or `None` to leave them alone.
+    pub placed: Option<Rgba<u8>>,
+    /// The colour for moderator actions, or `None` to leave them alone.
+    pub wiped: Option<Rgba<u8>>,
+}
+
+/// Lights up the pixels placed in the last few frames, fading out frame by frame.
+pub struct Highlights {
+    highlight: Highlight,
+    /// The frames left for each lit up pixel, by canvas position, and its colour.
+    active: HashMap<(i32, i32), (u32, Rgba<u8>)>,
+}
+
+impl Highlights {
+    pub fn new(highlight: Highlight) -> Self {
+        Highlights {
+            highlight,
+            active: HashMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.active.is_empty()
+    }
+
+    pub fn placed(&mut self, placement: &Placement) {
+        let colour = match placement.mod_action {
+            false => self.highlight.placed,
+            true => self.highlight.wiped,
+        };
+        if let Some(colour) = colour {
+            self.active.insert((placement.x, placement.y), (self.highlight.frames, colour));
+        }
+    }
+
+    /// Draws the highlights on `image`, which shows `expansion` laid out on `canvas`, and fades
+    /// them a frame further.
+    pub fn draw(&mut self, image: &mut RgbaImage, canvas: &FrameSize, expansion: &Expansion) {
+        // Where highlights overlap the strongest one wins, so a busy area doesn't turn solid.
+        let mut lit: HashMap<(i64, i64), (f32, Rgba<u8>)> = HashMap::new();
+        for (&(x, y), &(left, colour)) in &self.active {
+            let (frame_x, frame_y) = frame_position(canvas, expansion, x, y);
+            let strength = left as f32 / self.highlight.frames as f32;
+            let mut light = |dx: i64, dy: i64, strength: f32| {
+                let position = (frame_x as i64 + dx, frame_y as i64 + dy);
+                let entry = lit.entry(position).or_insert((0.0, colour));
+                if strength > entry.0 {
+                    *entry = (strength, colour);
+                }
+            };
+            match self.highlight.style {
+                HighlightStyle::Flash => light(0, 0, strength),
+                HighlightStyle::Outline => {
+                    for (dx, dy) in (-1..=1).flat_map(|dx| (-1..=1).map(move |dy| (dx, dy))) {
+                        // Only the ring, and not over pixels that are lit up themselves.
+                        if (dx, dy) != (0, 0) && !self.active.contains_key(&(x + dx as i32, y + dy as i32)) {
+                            light(dx, dy, strength);
+                        }
+                    }
+                }
+                HighlightStyle::Glow => {
+                    for dx in -GLOW_RADIUS..=GLOW_RADIUS {
+                        for dy in -GLOW_RADIUS..=GLOW_RADIUS {
+                            let distance = ((dx * dx + dy * dy) as f32).sqrt();
+                            let falloff = 1.0 - distance / (GLOW_RADIUS + 1) as f32;
+                            if falloff > 0.0 {
+                                light(dx, dy, strength * falloff);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // Glows and outlines stop at the edge of the canvas.
+        let (left, top) = frame_position(canvas, expansion, expansion.x, expansion.y);
+        let (left, top) = (left as i64, top as i64);
+        let (right, bottom) = (left + expansion.width as i64, top + expansion.height as i64);
+        for ((x, y), (strength, colour)) in lit {
+            if x < left || y < top || x >= right || y >= bottom {
+                continue;
+            }
+            let pixel = image.get_pixel_mut(x as u32, y as u32);
+            // Blank pixels are see-through white, and count as white here.
+            let base = match pixel[3] {
+                0 => [255, 255, 255],
+                _ => [pixel[0], pixel[1], pixel[2]],
+            };
+            let mix = |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * strength).round() as u8;
+            *pixel = Rgba([mix(base[0], colour[0]), mix(base[1], colour[1]), mix(base[2], colour[2]), 255]);
+        }
+
+        self.active.retain(|_, (left, _)| {
+            *left -= 1;
+            *left > 0
+        });
+    }
+}
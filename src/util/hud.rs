@@ -0,0 +1,116 @@
+use std::collections::{HashMap, VecDeque};
+
+use image::{Rgba, RgbaImage};
+
+use crate::config::Event;
+use crate::source::Placement;
+use crate::util::encoder::Encoding;
+use crate::util::expansion::Expansion;
+use crate::util::font::{draw_text, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::util::time::format_timestamp;
+
+const TEXT_COLOUR: Rgba<u8> = Rgba([255, 255, 255, 255]);
+/// How much of the frame shows through the box behind the text.
+const BACKGROUND_OPACITY: f32 = 0.35;
+
+/// The counters shown in the corner of the videos, updated as the replay goes.
+pub struct Hud {
+    start: i64,
+    active_seconds: i64,
+    size: u32,
+    placements: u64,
+    /// The placements within the active window, and how many each user has in there.
+    recent: VecDeque<(i64, u64)>,
+    active: HashMap<u64, u32>,
+}
+
+impl Hud {
+    /// Only when the video asked for it.
+    pub fn new(event: &Event, encoding: &Encoding) -> Option<Self> {
+        let active_minutes = encoding.hud?;
+        Some(Hud {
+            start: event.start,
+            active_seconds: active_minutes * 60,
+            size: encoding.frame.text_size(),
+            placements: 0,
+            recent: VecDeque::new(),
+            active: HashMap::new(),
+        })
+    }
+
+    /// Counts a placement, moderator actions aren't anyone's activity.
+    pub fn placed(&mut self, placement: &Placement) {
+        if placement.mod_action {
+            return;
+        }
+        self.placements += 1;
+        if placement.user != 0 {
+            self.recent.push_back((placement.timestamp, placement.user));
+            *self.active.entry(placement.user).or_default() += 1;
+        }
+    }
+
+    fn active_users(&mut self, timestamp: i64) -> usize {
+        while let Some(&(placed, user)) = self.recent.front() {
+            if placed > timestamp - self.active_seconds {
+                break;
+            }
+            self.recent.pop_front();
+            let count = self.active.get_mut(&user).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                self.active.remove(&user);
+            }
+        }
+        self.active.len()
+    }
+
+    pub fn draw(&mut self, image: &mut RgbaImage, timestamp: i64, expansion: &Expansion) {
+        let elapsed = (timestamp - self.start).max(0);
+        let lines = [
+            format!("{} UTC", format_timestamp(timestamp)),
+            format!(
+                "ELAPSED {}D {:02}:{:02}:{:02}",
+                elapsed / 86400,
+                elapsed / 3600 % 24,
+                elapsed / 60 % 60,
+                elapsed % 60
+            ),
+            format!("PLACED {}", thousands(self.placements)),
+            format!("ACTIVE {} ({}M)", self.active_users(timestamp), self.active_seconds / 60),
+            format!("CANVAS {}X{}", expansion.width, expansion.height),
+        ];
+
+        let size = self.size;
+        let longest = lines.iter().map(|line| line.len() as u32).max().unwrap();
+        let (width, height) = (
+            (longest * (GLYPH_WIDTH + 1) + 3) * size,
+            (lines.len() as u32 * (GLYPH_HEIGHT + 2) + 2) * size,
+        );
+        for x in 0..width.min(image.width()) {
+            for y in 0..height.min(image.height()) {
+                let pixel = image.get_pixel_mut(x, y);
+                // Transparent pixels are white in the video.
+                let [r, g, b] = if pixel.0[3] == 0 { [255; 3] } else { [pixel.0[0], pixel.0[1], pixel.0[2]] };
+                let [r, g, b] = [r, g, b].map(|channel| (channel as f32 * BACKGROUND_OPACITY) as u8);
+                *pixel = Rgba([r, g, b, 255]);
+            }
+        }
+        for (row, line) in lines.iter().enumerate() {
+            let top = (2 + row as u32 * (GLYPH_HEIGHT + 2)) * size;
+            draw_text(image, line, 2 * size, top, size, TEXT_COLOUR);
+        }
+    }
+}
+
+fn thousands(value: u64) -> String {
+    let digits = value.to_string();
+    let mut text = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            text.push(',');
+        }
+        text.push(digit);
+    }
+    text
+}
@@ -0,0 +1,248 @@
+use crate::source::{Placement, PlacementSource};
+use crate::util::expansion::{Expansion, ExpansionSchedule};
+use crate::util::replay::{CanvasReplay, ModActions, PixelState, ReplayView};
+use crate::util::time::TimeWindow;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use image::Rgba;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"CNVKEYFR";
+const VERSION: u32 = 2;
+
+/// Where a keyframe is in the index file.
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe {
+    /// Every placement at or before this time is in the keyframe.
+    pub timestamp: i64,
+    expansion_idx: u32,
+    offset: u64,
+    length: u64,
+}
+
+/// Full replay states every `interval` seconds, written by `canvas keyframes`, so the
+/// canvas at any time can be replayed from the keyframe before it instead of from the start.
+///
+/// After a header with the source fingerprint, the window start, the canvas bounds and the
+/// keyframe table, every keyframe is a zlib compressed copy of the pixel states, column by column.
+pub struct Keyframes {
+    path: PathBuf,
+    fingerprint: String,
+    since: i64,
+    bounds: Expansion,
+    keyframes: Vec<Keyframe>,
+}
+
+struct KeyframeWriter {
+    since: i64,
+    interval: i64,
+    next: i64,
+    placements: u64,
+    keyframes: Vec<(Keyframe, Vec<u8>)>,
+}
+
+impl ReplayView for KeyframeWriter {
+    fn before(&mut self, replay: &CanvasReplay, placement: &Placement) {
+        if placement.timestamp <= self.next {
+            return;
+        }
+        // Skip over quiet stretches instead of writing the same state over and over.
+        let timestamp =
+            self.next + (placement.timestamp - 1 - self.next) / self.interval * self.interval;
+        let (expansion_idx, pixels) = replay.state();
+        let data = encode(pixels);
+        let keyframe = Keyframe {
+            timestamp,
+            expansion_idx: expansion_idx as u32,
+            offset: 0,
+            length: data.len() as u64,
+        };
+        self.keyframes.push((keyframe, data));
+        self.next = timestamp + self.interval;
+    }
+
+    fn placed(&mut self, _replay: &CanvasReplay, _placement: &Placement, _previous: &PixelState) {
+        self.placements += 1;
+    }
+}
+
+impl Keyframes {
+    /// Reads the keyframe table, or returns `None` if there is no usable index at `path`.
+    pub fn open(path: PathBuf) -> Option<Self> {
+        let mut reader = BufReader::new(File::open(&path).ok()?);
+        let mut magic = [0; 8];
+        if reader.read_exact(&mut magic).is_err()
+            || &magic != MAGIC
+            || u32::from_le_bytes(read_bytes(&mut reader)) != VERSION
+        {
+            eprintln!("Ignoring {}, it isn't a keyframe index from this version", path.display());
+            return None;
+        }
+
+        let len = u32::from_le_bytes(read_bytes(&mut reader));
+        let mut fingerprint = vec![0; len as usize];
+        reader.read_exact(&mut fingerprint).expect("Truncated keyframe index");
+        let since = i64::from_le_bytes(read_bytes(&mut reader));
+        let bounds = Expansion {
+            x: i32::from_le_bytes(read_bytes(&mut reader)),
+            y: i32::from_le_bytes(read_bytes(&mut reader)),
+            width: u32::from_le_bytes(read_bytes(&mut reader)),
+            height: u32::from_le_bytes(read_bytes(&mut reader)),
+            at: 0,
+        };
+        let keyframes = (0..u32::from_le_bytes(read_bytes(&mut reader)))
+            .map(|_| Keyframe {
+                timestamp: i64::from_le_bytes(read_bytes(&mut reader)),
+                expansion_idx: u32::from_le_bytes(read_bytes(&mut reader)),
+                offset: u64::from_le_bytes(read_bytes(&mut reader)),
+                length: u64::from_le_bytes(read_bytes(&mut reader)),
+            })
+            .collect();
+
+        Some(Keyframes {
+            path,
+            fingerprint: String::from_utf8(fingerprint).expect("Invalid keyframe index"),
+            since,
+            bounds,
+            keyframes,
+        })
+    }
+
+    /// Replays every placement in `window` from `source`, keeping a keyframe every `interval` seconds.
+    pub async fn write(
+        source: &impl PlacementSource,
+        schedule: &ExpansionSchedule,
+        window: &TimeWindow,
+        path: &Path,
+        interval: i64,
+    ) {
+        let fingerprint = source.fingerprint().await;
+        let mut writer = KeyframeWriter {
+            since: window.since,
+            interval,
+            next: window.since + interval,
+            placements: 0,
+            keyframes: vec![],
+        };
+        // Rollbacks need the history under every pixel, which keyframes don't keep.
+        let mut replay = CanvasReplay::new(schedule, ModActions::Wipe);
+        replay.run(source.placements(window), &mut writer).await;
+
+        let bounds = replay.bounds();
+        let header_len = (MAGIC.len() + 4 + 4 + fingerprint.len() + 8 + 16 + 4) as u64;
+        let mut offset = header_len + writer.keyframes.len() as u64 * 28;
+        for (keyframe, _) in &mut writer.keyframes {
+            keyframe.offset = offset;
+            offset += keyframe.length;
+        }
+
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let file = File::create(&tmp_path)
+            .unwrap_or_else(|e| panic!("Failed to create {}: {e}", path.display()));
+        let mut file = BufWriter::new(file);
+        file.write_all(MAGIC).unwrap();
+        file.write_all(&VERSION.to_le_bytes()).unwrap();
+        file.write_all(&(fingerprint.len() as u32).to_le_bytes()).unwrap();
+        file.write_all(fingerprint.as_bytes()).unwrap();
+        file.write_all(&writer.since.to_le_bytes()).unwrap();
+        file.write_all(&bounds.x.to_le_bytes()).unwrap();
+        file.write_all(&bounds.y.to_le_bytes()).unwrap();
+        file.write_all(&bounds.width.to_le_bytes()).unwrap();
+        file.write_all(&bounds.height.to_le_bytes()).unwrap();
+        file.write_all(&(writer.keyframes.len() as u32).to_le_bytes()).unwrap();
+        for (keyframe, _) in &writer.keyframes {
+            file.write_all(&keyframe.timestamp.to_le_bytes()).unwrap();
+            file.write_all(&keyframe.expansion_idx.to_le_bytes()).unwrap();
+            file.write_all(&keyframe.offset.to_le_bytes()).unwrap();
+            file.write_all(&keyframe.length.to_le_bytes()).unwrap();
+        }
+        for (_, data) in &writer.keyframes {
+            file.write_all(data).unwrap();
+        }
+        file.flush().unwrap();
+        drop(file);
+        fs::rename(&tmp_path, path).unwrap();
+
+        println!(
+            "Wrote {} keyframes of {} placements to {}",
+            writer.keyframes.len(),
+            writer.placements,
+            path.display()
+        );
+    }
+
+    /// Whether the index was made from this source, for this window start and canvas.
+    pub fn matches(&self, fingerprint: &str, since: i64, schedule: &ExpansionSchedule) -> bool {
+        let bounds = schedule.bounds();
+        self.fingerprint == fingerprint
+            && self.since == since
+            && (self.bounds.x, self.bounds.y, self.bounds.width, self.bounds.height)
+                == (bounds.x, bounds.y, bounds.width, bounds.height)
+    }
+
+    /// The last keyframe at or before `timestamp`.
+    pub fn before(&self, timestamp: i64) -> Option<&Keyframe> {
+        self.keyframes
+            .iter()
+            .take_while(|keyframe| keyframe.timestamp <= timestamp)
+            .last()
+    }
+
+    /// Restores the replay as it was at `keyframe`.
+    pub fn load<'a>(&self, keyframe: &Keyframe, schedule: &'a ExpansionSchedule) -> CanvasReplay<'a> {
+        let mut file = File::open(&self.path).unwrap();
+        file.seek(SeekFrom::Start(keyframe.offset)).unwrap();
+        let pixels = decode(file.take(keyframe.length), (self.bounds.width * self.bounds.height) as usize);
+        CanvasReplay::restore(schedule, keyframe.expansion_idx as usize, pixels)
+    }
+}
+
+// Each field gets its own column, which compresses a lot better than whole pixels.
+fn encode(pixels: &[PixelState]) -> Vec<u8> {
+    let mut columns = Vec::with_capacity(pixels.len() * 32);
+    columns.extend(pixels.iter().flat_map(|pixel| pixel.color.0));
+    columns.extend(pixels.iter().flat_map(|pixel| pixel.owner.to_le_bytes()));
+    columns.extend(pixels.iter().flat_map(|pixel| time_bytes(pixel.last_change)));
+    columns.extend(pixels.iter().flat_map(|pixel| pixel.changes.to_le_bytes()));
+    columns.extend(pixels.iter().flat_map(|pixel| time_bytes(pixel.first_touch)));
+
+    let mut encoder = ZlibEncoder::new(vec![], Compression::fast());
+    encoder.write_all(&columns).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn decode(reader: impl Read, len: usize) -> Vec<PixelState> {
+    let mut decoder = BufReader::new(ZlibDecoder::new(reader));
+    let mut pixels = vec![PixelState::UNTOUCHED; len];
+    let time = |timestamp: i64| (timestamp != i64::MIN).then_some(timestamp);
+    for pixel in &mut pixels {
+        pixel.color = Rgba(read_bytes(&mut decoder));
+    }
+    for pixel in &mut pixels {
+        pixel.owner = u64::from_le_bytes(read_bytes(&mut decoder));
+    }
+    for pixel in &mut pixels {
+        pixel.last_change = time(i64::from_le_bytes(read_bytes(&mut decoder)));
+    }
+    for pixel in &mut pixels {
+        pixel.changes = u32::from_le_bytes(read_bytes(&mut decoder));
+    }
+    for pixel in &mut pixels {
+        pixel.first_touch = time(i64::from_le_bytes(read_bytes(&mut decoder)));
+    }
+    pixels
+}
+
+fn time_bytes(timestamp: Option<i64>) -> [u8; 8] {
+    timestamp.unwrap_or(i64::MIN).to_le_bytes()
+}
+
+fn read_bytes<const N: usize>(reader: &mut impl Read) -> [u8; N] {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes).expect("Truncated keyframe index");
+    bytes
+}
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+use clap::ValueEnum;
+use image::{Rgba, RgbaImage};
+use serde::Deserialize;
+
+use crate::source::{Placement, User};
+use crate::util::color::UserColors;
+use crate::util::font::{draw_text, fill, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::util::render::FrameSize;
+use crate::util::replay::{CanvasReplay, PixelState};
+
+const BACKGROUND: Rgba<u8> = Rgba([24, 24, 24, 255]);
+const TEXT_COLOUR: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const NAME_LENGTH: usize = 14;
+/// A rank, the name and the count: `10 SomeUsername   1234567`.
+const LINE_LENGTH: u32 = 2 + 1 + NAME_LENGTH as u32 + 1 + 7;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LeaderboardMode {
+    /// Everything a user placed so far.
+    Placements,
+    /// The pixels a user owns on the canvas right now, like `currentpixels`.
+    Pixels,
+}
+
+/// The top placers in a panel on the right of the frame, updated as the replay goes.
+pub struct Leaderboard {
+    mode: LeaderboardMode,
+    size: usize,
+    users: HashMap<u64, User>,
+    colors: UserColors,
+    counts: HashMap<u64, i64>,
+}
+
+impl Leaderboard {
+    /// The width of the panel in font pixels: a margin, the swatch, a gap, the text and a margin.
+    pub const WIDTH: u32 = 2 + GLYPH_HEIGHT + 2 + LINE_LENGTH * (GLYPH_WIDTH + 1) - 1 + 2;
+
+    pub fn new(mode: LeaderboardMode, size: usize, users: HashMap<u64, User>) -> Self {
+        Leaderboard {
+            mode,
+            size,
+            users,
+            colors: UserColors::load(),
+            counts: HashMap::new(),
+        }
+    }
+
+    pub fn placed(&mut self, replay: &CanvasReplay, placement: &Placement, previous: &PixelState) {
+        match self.mode {
+            LeaderboardMode::Placements if !placement.mod_action => {
+                *self.counts.entry(placement.user).or_default() += 1;
+            }
+            LeaderboardMode::Placements => {}
+            LeaderboardMode::Pixels => {
+                let owner = replay.pixel(placement.x, placement.y).owner;
+                if owner != previous.owner {
+                    *self.counts.entry(previous.owner).or_default() -= 1;
+                    *self.counts.entry(owner).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    pub fn draw(&mut self, image: &mut RgbaImage, frame: &FrameSize) {
+        let size = frame.text_size();
+        let left = frame.width - frame.sidebar;
+        fill(image, left, 0, frame.sidebar, frame.height, BACKGROUND);
+
+        let title = match self.mode {
+            LeaderboardMode::Placements => "MOST PLACED",
+            LeaderboardMode::Pixels => "MOST PIXELS",
+        };
+        draw_text(image, title, left + 2 * size, 2 * size, size, TEXT_COLOUR);
+
+        // Like `currentpixels`, banned users and placements nobody can be linked to are left out.
+        let mut top = self
+            .counts
+            .iter()
+            .filter(|(user, count)| **count > 0 && self.users.get(user).is_some_and(|user| !user.banned))
+            .map(|(user, count)| (*user, *count))
+            .collect::<Vec<_>>();
+        top.sort_by(|(a_user, a), (b_user, b)| b.cmp(a).then(a_user.cmp(b_user)));
+
+        for (i, (user, count)) in top.into_iter().take(self.size).enumerate() {
+            let top = (2 + (i as u32 + 1) * (GLYPH_HEIGHT + 2) + 2) * size;
+            let swatch = self.colors.get(user);
+            fill(image, left + 2 * size, top, GLYPH_HEIGHT * size, GLYPH_HEIGHT * size, swatch);
+
+            let name = self.users[&user].name.chars().take(NAME_LENGTH).collect::<String>();
+            let line = format!("{:>2} {name:<NAME_LENGTH$} {count:>7}", i + 1);
+            draw_text(image, &line, left + (2 + GLYPH_HEIGHT + 2) * size, top, size, TEXT_COLOUR);
+        }
+    }
+}
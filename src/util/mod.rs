@@ -1,5 +1,15 @@
+pub mod camera;
 pub mod canvas;
 pub mod color;
-pub mod db;
+pub mod encoder;
+pub mod expansion;
+pub mod font;
+pub mod highlight;
+pub mod hud;
 pub mod io;
+pub mod keyframes;
+pub mod leaderboard;
 pub mod render;
+pub mod replay;
+pub mod time;
+pub mod video;
@@ -1,68 +1,289 @@
-use std::process::{Child, ChildStdin, Command};
-
-use aformat::aformat;
+use clap::ValueEnum;
 use image::{ImageBuffer, Rgba};
-use to_arraystring::ToArrayString;
+use serde::Deserialize;
 
-use crate::config::CANVAS_SIZES;
+use crate::util::expansion::Expansion;
+use crate::util::font::text_size;
 
-const IMAGE_SIZE: (u32, u32) = (960, 540);
-const VIDEO_SCALE: u32 = 2;
+const DEFAULT_SCALE: u32 = 2;
 const WHITE: Rgba<u8> = Rgba([255, 255, 255, 0]);
 pub const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
 
-pub fn start_ffmpeg(fps: u8, filename: &str) -> std::io::Result<(Child, ChildStdin)> {
-    #[rustfmt::skip]
-    let mut child = Command::new("ffmpeg")
-        .args([
-            "-framerate", &fps.to_arraystring(),
-            "-f", "rawvideo",
-            "-pix_fmt", "rgba",
-            "-video_size", &aformat!("{}x{}", IMAGE_SIZE.0, IMAGE_SIZE.1),
-            "-i", "pipe:0",
-            "-c:v", "libx264",
-            "-pix_fmt", "yuv420p",
-            "-preset", "veryslow",
-            "-y",
-            "-vf", &aformat!("scale={}:{}:flags=neighbor", IMAGE_SIZE.0 * VIDEO_SCALE, IMAGE_SIZE.1 * VIDEO_SCALE),
-            "-crf", "24",
-            "-tune", "animation",
-            "-keyint_min", "64",
-            format!("./output/{filename}.mp4").as_str(),
-        ])
-        .stdin(std::process::Stdio::piped())
-        .spawn()?;
-
-    let stdin = child.stdin.take().expect("Failed to open stdin");
-
-    Ok((child, stdin))
+/// How the timelapse renderers decide when to write a frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PacingMode {
+    /// After `pixels_per_frame` placements, but at most once every `min_seconds_between_frames` seconds.
+    #[default]
+    Pixels,
+    /// Every `seconds_per_frame` seconds of the event, quiet or not, so video time runs like event time.
+    Time,
+    /// Once the event time and the placements since the last frame add up to a whole frame of
+    /// `seconds_per_frame` and `pixels_per_frame`. Quiet stretches go by fast, and bursts slow down.
+    Adaptive,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Pacing {
+    pub mode: PacingMode,
+    pub frames_per_second: u8,
+    pub pixels_per_frame: i32,
+    pub min_seconds_between_frames: i64,
+    pub seconds_per_frame: i64,
+}
+
+impl Pacing {
+    /// Solves for the frame budgets that make a video of about `video_seconds`, out of
+    /// `event_seconds` of event with `placements` placements.
+    pub fn fit(&mut self, video_seconds: i64, event_seconds: i64, placements: u64) {
+        let frames = (video_seconds.max(0) as u64 * self.frames_per_second as u64).max(1);
+        let event_seconds = event_seconds.max(0) as u64;
+        match self.mode {
+            PacingMode::Pixels => {
+                self.pixels_per_frame = placements.div_ceil(frames).max(1) as i32;
+                self.min_seconds_between_frames = 0;
+            }
+            PacingMode::Time => {
+                self.seconds_per_frame = event_seconds.div_ceil(frames).max(1) as i64;
+            }
+            // Half the frames come from time passing, the other half from placements.
+            PacingMode::Adaptive => {
+                self.seconds_per_frame = (2 * event_seconds).div_ceil(frames).max(1) as i64;
+                self.pixels_per_frame = (2 * placements).div_ceil(frames).max(1) as i32;
+            }
+        }
+    }
+}
+
+/// Decides when the timelapse renderers write a frame, as set by the `Pacing`.
+pub struct FramePacer {
+    pacing: Pacing,
+    remaining_pixels: i32,
+    frame_start_time: i64,
+    placements_since_frame: i32,
+    latest: i64,
+}
+
+impl FramePacer {
+    /// Starts pacing at `since`, the start of the time window.
+    pub fn new(pacing: &Pacing, since: i64) -> Self {
+        FramePacer {
+            pacing: *pacing,
+            remaining_pixels: 0,
+            frame_start_time: match pacing.mode {
+                PacingMode::Pixels => 0,
+                PacingMode::Time | PacingMode::Adaptive => since,
+            },
+            placements_since_frame: 0,
+            latest: since,
+        }
+    }
+
+    /// The times of the frames to write before the placement at `timestamp`. Only more than one
+    /// when pacing by time, to show a quiet stretch for as long as it lasted.
+    pub fn frames_due(&mut self, timestamp: i64) -> impl Iterator<Item = i64> {
+        let pacing = self.pacing;
+        let frame_start_time = self.frame_start_time;
+        self.latest = timestamp;
+        let frames = match pacing.mode {
+            PacingMode::Pixels => {
+                let due = self.remaining_pixels <= 0
+                    && timestamp - self.frame_start_time >= pacing.min_seconds_between_frames;
+                if due {
+                    self.remaining_pixels = pacing.pixels_per_frame;
+                    self.frame_start_time = timestamp;
+                }
+                self.remaining_pixels -= 1;
+                due as usize
+            }
+            PacingMode::Time => {
+                let elapsed = timestamp - self.frame_start_time;
+                let frames = if elapsed > 0 { (elapsed - 1) / pacing.seconds_per_frame } else { 0 };
+                self.frame_start_time += frames * pacing.seconds_per_frame;
+                frames as usize
+            }
+            PacingMode::Adaptive => {
+                let progress = (timestamp - self.frame_start_time) as f64 / pacing.seconds_per_frame as f64
+                    + self.placements_since_frame as f64 / pacing.pixels_per_frame as f64;
+                let due = progress >= 1.0;
+                if due {
+                    self.frame_start_time = timestamp;
+                }
+                due as usize
+            }
+        };
+        if frames > 0 {
+            self.placements_since_frame = 0;
+        }
+        self.placements_since_frame += 1;
+        (1..=frames as i64).map(move |frame| match pacing.mode {
+            PacingMode::Time => frame_start_time + frame * pacing.seconds_per_frame,
+            PacingMode::Pixels | PacingMode::Adaptive => timestamp,
+        })
+    }
+
+    /// The time of the last frame, if there were placements since the frame before.
+    pub fn pending(&self) -> Option<i64> {
+        (self.placements_since_frame > 0).then_some(self.latest)
+    }
+}
+
+/// The size of the video frames in canvas pixels, and how they are blown up for the video.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameSize {
+    pub width: u32,
+    pub height: u32,
+    /// The width of the panel on the right, which the canvas is centred next to.
+    pub sidebar: u32,
+    /// Every canvas pixel becomes `scale` by `scale` pixels in the video.
+    pub scale: u32,
+    /// Every `shrink` by `shrink` canvas pixels become one pixel in the video instead, when the
+    /// frame is bigger than the resolution. `scale` is 1 then.
+    pub shrink: u32,
+    /// The size of the video, with black bars around the scaled frame when it doesn't fill it.
+    pub output: (u32, u32),
+}
+
+impl FrameSize {
+    /// Fits `content`, usually the largest expansion, with `padding` canvas pixels around it, and a `sidebar` as wide as
+    /// that many font pixels next to it. With a `resolution`, the frame fills it at the largest scale
+    /// that fits, otherwise the video is the frame times `scale`.
+    ///
+    /// Content too big for the resolution is shrunk to fit, unless it is `cropped` by a camera, which
+    /// only shows the part of it the resolution has room for.
+    pub fn fit(
+        content: (u32, u32),
+        padding: u32,
+        sidebar: u32,
+        scale: Option<u32>,
+        resolution: Option<(u32, u32)>,
+        cropped: bool,
+    ) -> Self {
+        let (width, height) = (content.0 + 2 * padding, content.1 + 2 * padding);
+        // The text gets smaller in frame pixels as the scale goes up, and so does the sidebar.
+        let sidebar_at = |scale: u32, shrink: u32| sidebar * text_size(scale) * shrink;
+        let Some((output_width, output_height)) = resolution else {
+            let scale = scale.unwrap_or(DEFAULT_SCALE).max(1);
+            let width = width + sidebar_at(scale, 1);
+            // Encoders want even sizes.
+            let (width, height) = (width + width * scale % 2, height + height * scale % 2);
+            return FrameSize {
+                width,
+                height,
+                sidebar: sidebar_at(scale, 1),
+                scale,
+                shrink: 1,
+                output: (width * scale, height * scale),
+            };
+        };
+
+        let fits = |scale: u32| {
+            scale >= 1
+                && (width + sidebar_at(scale, 1)) * scale <= output_width
+                && height * scale <= output_height
+        };
+        let largest = (output_width / width).min(output_height / height);
+        let scale = match scale {
+            Some(scale) if fits(scale) => scale,
+            Some(scale) if scale > 1 => panic!(
+                "a {width}x{height} canvas doesn't fit in {output_width}x{output_height} at scale {scale}, lower the scale or padding"
+            ),
+            _ => (1..=largest).rev().find(|&scale| fits(scale)).unwrap_or(0),
+        };
+        if scale > 0 || cropped {
+            let scale = scale.max(1);
+            return FrameSize {
+                width: output_width / scale,
+                height: output_height / scale,
+                sidebar: sidebar_at(scale, 1),
+                scale,
+                shrink: 1,
+                output: (output_width, output_height),
+            };
+        }
+
+        // Shrunk just enough for the content and the sidebar, which shrinks along with it, to fit.
+        let room = output_width.checked_sub(sidebar_at(1, 1)).filter(|room| *room > 0);
+        let room = room.unwrap_or_else(|| panic!("the sidebar doesn't fit in {output_width}x{output_height}"));
+        let shrink = width.div_ceil(room).max(height.div_ceil(output_height));
+        FrameSize {
+            width: output_width * shrink,
+            height: output_height * shrink,
+            sidebar: sidebar_at(1, shrink),
+            scale: 1,
+            shrink,
+            output: (output_width, output_height),
+        }
+    }
+
+    /// The size of the frame in the video, before the black bars around it.
+    pub fn scaled(&self) -> (u32, u32) {
+        (
+            self.width * self.scale / self.shrink,
+            self.height * self.scale / self.shrink,
+        )
+    }
+
+    /// How many frame pixels each font pixel is, so text is as readable however the frame is scaled.
+    pub fn text_size(&self) -> u32 {
+        text_size(self.scale) * self.shrink
+    }
 }
 
-pub fn pixel_offset(canvas_size_idx: usize) -> (u32, u32) {
+/// Parses a video resolution, like `1920x1080`, `1080p`, `4k` or `square`.
+pub fn parse_resolution(text: &str) -> Result<(u32, u32), String> {
+    match text.to_lowercase().as_str() {
+        "720p" => return Ok((1280, 720)),
+        "1080p" => return Ok((1920, 1080)),
+        "1440p" => return Ok((2560, 1440)),
+        "4k" | "2160p" => return Ok((3840, 2160)),
+        "square" => return Ok((1080, 1080)),
+        _ => {}
+    }
+    let (width, height) = text
+        .split_once('x')
+        .ok_or(format!("invalid resolution '{text}', use WIDTHxHEIGHT, 720p, 1080p, 1440p, 4k or square"))?;
+    let parse = |value: &str| value.trim().parse::<u32>().map_err(|e| format!("invalid resolution '{text}': {e}"));
+    Ok((parse(width)?, parse(height)?))
+}
+
+pub fn pixel_offset(frame: &FrameSize, expansion: &Expansion) -> (u32, u32) {
     (
-        (IMAGE_SIZE.0 - CANVAS_SIZES[canvas_size_idx].0) / 2,
-        (IMAGE_SIZE.1 - CANVAS_SIZES[canvas_size_idx].1) / 2,
+        (frame.width - frame.sidebar - expansion.width) / 2,
+        (frame.height - expansion.height) / 2,
     )
 }
 
-pub fn blank_image_borders(canvas_size_idx: usize, invert: bool) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+/// Where a placement ends up in the frame while `expansion` is the current canvas.
+pub fn frame_position(frame: &FrameSize, expansion: &Expansion, x: i32, y: i32) -> (u32, u32) {
+    let (x_offset, y_offset) = pixel_offset(frame, expansion);
+    let (x, y) = expansion.local(x, y);
+    (x + x_offset, y + y_offset)
+}
+
+pub fn blank_image_borders(
+    frame: &FrameSize,
+    expansion: &Expansion,
+    invert: bool,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
     if invert {
-        return blank_image_borders_with_colour(canvas_size_idx, WHITE, BLACK);
+        return blank_image_borders_with_colour(frame, expansion, WHITE, BLACK);
     }
-    blank_image_borders_with_colour(canvas_size_idx, BLACK, WHITE)
+    blank_image_borders_with_colour(frame, expansion, BLACK, WHITE)
 }
 
 pub fn blank_image_borders_with_colour(
-    canvas_size_idx: usize,
+    frame: &FrameSize,
+    expansion: &Expansion,
     in_bounds: Rgba<u8>,
     out_of_bounds: Rgba<u8>,
 ) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
-    let mut image = image::ImageBuffer::new(IMAGE_SIZE.0, IMAGE_SIZE.1);
-    let (width, height) = CANVAS_SIZES[canvas_size_idx];
-    let (x_offset, y_offset) = pixel_offset(canvas_size_idx);
+    let mut image = image::ImageBuffer::new(frame.width, frame.height);
+    let (width, height) = (expansion.width, expansion.height);
+    let (x_offset, y_offset) = pixel_offset(frame, expansion);
     for x in 0..image.width() {
         for y in 0..image.height() {
-            if x < x_offset || y < y_offset || x > x_offset + width || y > y_offset + height {
+            if x < x_offset || y < y_offset || x >= x_offset + width || y >= y_offset + height {
                 image.put_pixel(x, y, out_of_bounds);
             } else {
                 image.put_pixel(x, y, in_bounds);
@@ -74,32 +295,29 @@ pub fn blank_image_borders_with_colour(
 }
 
 pub fn extend_canvas(
+    frame: &FrameSize,
     image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
-    canvas_size_idx: usize,
+    old: &Expansion,
+    new: &Expansion,
 ) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
-    extend_canvas_with_colour(image, canvas_size_idx, WHITE, BLACK)
+    extend_canvas_with_colour(frame, image, old, new, WHITE, BLACK)
 }
 
 pub fn extend_canvas_with_colour(
+    frame: &FrameSize,
     image: &ImageBuffer<Rgba<u8>, Vec<u8>>,
-    canvas_size_idx: usize,
+    old: &Expansion,
+    new: &Expansion,
     in_bounds: Rgba<u8>,
     out_of_bounds: Rgba<u8>,
 ) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
-    let old_offset = pixel_offset(canvas_size_idx - 1);
-    let (old_width, old_height) = CANVAS_SIZES[canvas_size_idx - 1];
-    let new_offset = pixel_offset(canvas_size_idx);
-    let mut new_image = blank_image_borders_with_colour(canvas_size_idx, in_bounds, out_of_bounds);
-
-    for x in 0..old_width {
-        for y in 0..old_height {
-            let old_x = x + old_offset.0;
-            let old_y = y + old_offset.1;
-            new_image.put_pixel(
-                x + new_offset.0,
-                y + new_offset.1,
-                *image.get_pixel(old_x, old_y),
-            );
+    let mut new_image = blank_image_borders_with_colour(frame, new, in_bounds, out_of_bounds);
+
+    for x in old.x..old.x + old.width as i32 {
+        for y in old.y..old.y + old.height as i32 {
+            let (old_x, old_y) = frame_position(frame, old, x, y);
+            let (new_x, new_y) = frame_position(frame, new, x, y);
+            new_image.put_pixel(new_x, new_y, *image.get_pixel(old_x, old_y));
         }
     }
 
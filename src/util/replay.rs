@@ -0,0 +1,236 @@
+use crate::config::Event;
+use crate::source::{Placement, PlacementSource};
+use crate::util::expansion::{Expansion, ExpansionSchedule};
+use crate::util::time::TimeWindow;
+use clap::ValueEnum;
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use image::Rgba;
+use serde::Deserialize;
+
+/// Everything the replay knows about one pixel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PixelState {
+    /// Transparent until something is placed.
+    pub color: Rgba<u8>,
+    /// The user whose placement is showing, or 0 when it is nobody's: untouched,
+    /// wiped by a moderator or placed by an unknown user.
+    pub owner: u64,
+    pub last_change: Option<i64>,
+    /// How many placements landed on the pixel, moderator actions included.
+    pub changes: u32,
+    pub first_touch: Option<i64>,
+}
+
+impl PixelState {
+    pub const UNTOUCHED: PixelState = PixelState {
+        color: Rgba([0, 0, 0, 0]),
+        owner: 0,
+        last_change: None,
+        changes: 0,
+        first_touch: None,
+    };
+
+    /// Whether nothing is showing, because nothing was placed or it was all rolled back.
+    pub fn is_blank(&self) -> bool {
+        self.color == PixelState::UNTOUCHED.color
+    }
+}
+
+/// What a moderator action does to the pixel it lands on, the same for every module.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ModActions {
+    /// Paint the moderator's colour, owned by nobody.
+    #[default]
+    Wipe,
+    /// Undo the placement that is showing, bringing back the one before it.
+    Rollback,
+}
+
+/// A module's view of the replay. Every hook does nothing by default, so views
+/// only implement what they need, and static images can just read the final state.
+pub trait ReplayView {
+    /// Before a placement is applied, while the canvas is still as it was.
+    fn before(&mut self, _replay: &CanvasReplay, _placement: &Placement) {}
+
+    /// After the canvas grew, before the placement that made it grow is applied.
+    fn expanded(&mut self, _replay: &CanvasReplay, _previous: &Expansion) {}
+
+    /// After a placement is applied, with the pixel as it was before.
+    fn placed(&mut self, _replay: &CanvasReplay, _placement: &Placement, _previous: &PixelState) {}
+}
+
+impl ReplayView for () {}
+
+/// Applies placements in order, keeping the state of every pixel on the canvas.
+///
+/// Moderator actions are applied as set by `ModActions`. Either way they count as a change
+/// of the pixel, by nobody.
+pub struct CanvasReplay<'a> {
+    schedule: &'a ExpansionSchedule,
+    mod_actions: ModActions,
+    bounds: Expansion,
+    expansion_idx: usize,
+    pixels: Vec<PixelState>,
+    /// Per pixel, the colour and owner under every user placement that is still showing.
+    /// Only kept for rollbacks.
+    history: Vec<Vec<(Rgba<u8>, u64)>>,
+}
+
+impl<'a> CanvasReplay<'a> {
+    pub fn new(schedule: &'a ExpansionSchedule, mod_actions: ModActions) -> Self {
+        let bounds = schedule.bounds();
+        let len = (bounds.width * bounds.height) as usize;
+        CanvasReplay {
+            schedule,
+            mod_actions,
+            bounds,
+            expansion_idx: 0,
+            pixels: vec![PixelState::UNTOUCHED; len],
+            history: match mod_actions {
+                ModActions::Wipe => vec![],
+                ModActions::Rollback => vec![vec![]; len],
+            },
+        }
+    }
+
+    /// Picks up where a keyframe left off. Keyframes have no history, so only for wipes.
+    pub fn restore(schedule: &'a ExpansionSchedule, expansion_idx: usize, pixels: Vec<PixelState>) -> Self {
+        let bounds = schedule.bounds();
+        assert_eq!(pixels.len(), (bounds.width * bounds.height) as usize);
+        CanvasReplay {
+            schedule,
+            mod_actions: ModActions::Wipe,
+            bounds,
+            expansion_idx,
+            pixels,
+            history: vec![],
+        }
+    }
+
+    /// The canvas at the end of the event window, replayed from the last keyframe before it when there is one.
+    pub async fn at(source: &impl PlacementSource, event: &'a Event) -> Self {
+        let keyframe = event
+            .keyframes
+            .as_ref()
+            .filter(|_| event.mod_actions == ModActions::Wipe)
+            .and_then(|keyframes| {
+                let keyframe = keyframes.before(event.window.until.unwrap_or(i64::MAX))?;
+                Some((keyframes, *keyframe))
+            });
+        let (mut replay, window) = match keyframe {
+            Some((keyframes, keyframe)) => (
+                keyframes.load(&keyframe, &event.schedule),
+                TimeWindow {
+                    since: keyframe.timestamp,
+                    until: event.window.until,
+                },
+            ),
+            None => (
+                CanvasReplay::new(&event.schedule, event.mod_actions),
+                event.window.clone(),
+            ),
+        };
+        // Sources compare the window start as text, which lets placements from later in the
+        // keyframe's last second through again.
+        let since = window.since;
+        let placements = source.placements(&window).filter(move |pixel| {
+            let later = pixel.timestamp > since;
+            async move { later }
+        });
+        replay.run(placements.boxed(), &mut ()).await;
+        replay
+    }
+
+    /// Replays every placement, calling the view's hooks along the way.
+    pub async fn run(&mut self, mut placements: BoxStream<'_, Placement>, view: &mut impl ReplayView) {
+        while let Some(placement) = placements.next().await {
+            view.before(self, &placement);
+
+            let idx = self.schedule.index_for(
+                self.expansion_idx,
+                placement.timestamp,
+                placement.x,
+                placement.y,
+            );
+            if idx != self.expansion_idx {
+                let previous = *self.expansion();
+                self.expansion_idx = idx;
+                view.expanded(self, &previous);
+            }
+
+            let previous = self.apply(&placement);
+            view.placed(self, &placement, &previous);
+        }
+    }
+
+    /// Applies one placement, returning the pixel as it was before.
+    pub fn apply(&mut self, placement: &Placement) -> PixelState {
+        let idx = self.index(placement.x, placement.y);
+        let pixel = &mut self.pixels[idx];
+        let previous = *pixel;
+        match (placement.mod_action, self.mod_actions) {
+            (false, mod_actions) => {
+                if mod_actions == ModActions::Rollback {
+                    self.history[idx].push((pixel.color, pixel.owner));
+                }
+                pixel.color = placement.color;
+                pixel.owner = placement.user;
+            }
+            (true, ModActions::Wipe) => {
+                pixel.color = placement.color;
+                pixel.owner = 0;
+            }
+            (true, ModActions::Rollback) => {
+                let (color, owner) = self.history[idx]
+                    .pop()
+                    .unwrap_or((PixelState::UNTOUCHED.color, 0));
+                pixel.color = color;
+                pixel.owner = owner;
+            }
+        }
+        pixel.last_change = Some(placement.timestamp);
+        pixel.changes += 1;
+        pixel.first_touch.get_or_insert(placement.timestamp);
+        previous
+    }
+
+    /// The pixel at canvas coordinates `x` and `y`.
+    pub fn pixel(&self, x: i32, y: i32) -> &PixelState {
+        &self.pixels[self.index(x, y)]
+    }
+
+    /// The canvas as it currently is.
+    pub fn expansion(&self) -> &Expansion {
+        self.schedule.get(self.expansion_idx)
+    }
+
+    /// The area covering every expansion, which the pixel grid is sized to.
+    pub fn bounds(&self) -> &Expansion {
+        &self.bounds
+    }
+
+    /// The current expansion index and every pixel, for keyframes.
+    pub fn state(&self) -> (usize, &[PixelState]) {
+        (self.expansion_idx, &self.pixels)
+    }
+
+    /// Every pixel, with coordinates relative to the top left of `bounds()`.
+    pub fn pixels(&self) -> impl Iterator<Item = (u32, u32, &PixelState)> {
+        let width = self.bounds.width;
+        self.pixels
+            .iter()
+            .enumerate()
+            .map(move |(idx, pixel)| (idx as u32 % width, idx as u32 / width, pixel))
+    }
+
+    fn index(&self, x: i32, y: i32) -> usize {
+        assert!(
+            self.bounds.contains(x, y),
+            "placement at ({x}, {y}) is outside the canvas, see `canvas check`"
+        );
+        let (x, y) = self.bounds.local(x, y);
+        (y * self.bounds.width + x) as usize
+    }
+}
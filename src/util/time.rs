@@ -0,0 +1,194 @@
+use crate::source::PlacementSource;
+
+const FAR_FUTURE: &str = "9999-12-31 23:59:59";
+
+/// A `--since` / `--until` value, before the event bounds are known.
+#[derive(Clone, Copy, Debug)]
+pub enum TimeArg {
+    Absolute(i64),
+    FromStart(i64),
+    FromEnd(i64),
+}
+
+#[derive(Clone, Debug)]
+pub struct TimeWindow {
+    pub since: i64,
+    pub until: Option<i64>,
+}
+
+impl TimeWindow {
+    pub fn since_sql(&self) -> String {
+        format_timestamp(self.since)
+    }
+
+    pub fn until_sql(&self) -> String {
+        match self.until {
+            Some(until) => format_timestamp(until),
+            None => FAR_FUTURE.to_string(),
+        }
+    }
+
+    pub fn contains(&self, timestamp: i64) -> bool {
+        timestamp > self.since && self.until.is_none_or(|until| timestamp <= until)
+    }
+}
+
+pub async fn resolve_window(
+    source: &impl PlacementSource,
+    event_start: i64,
+    event_end: Option<i64>,
+    since: Option<TimeArg>,
+    until: Option<TimeArg>,
+) -> TimeWindow {
+    let needs_end = matches!(until, Some(TimeArg::FromEnd(_)))
+        || (matches!(since, Some(TimeArg::FromEnd(_))) && until.is_none());
+    let event_end = match event_end {
+        Some(event_end) => event_end,
+        None if needs_end => source.last_placement().await.unwrap_or(event_start),
+        None => event_start,
+    };
+
+    let until = match until {
+        Some(TimeArg::Absolute(timestamp)) => Some(timestamp),
+        Some(TimeArg::FromStart(seconds)) => Some(event_start + seconds),
+        Some(TimeArg::FromEnd(seconds)) => Some(event_end - seconds),
+        None if event_end > event_start => Some(event_end),
+        None => None,
+    };
+    let since = match since {
+        Some(TimeArg::Absolute(timestamp)) => timestamp,
+        Some(TimeArg::FromStart(seconds)) => event_start + seconds,
+        Some(TimeArg::FromEnd(seconds)) => until.unwrap_or(event_end) - seconds,
+        None => event_start,
+    };
+
+    TimeWindow { since, until }
+}
+
+/// Parses an absolute timestamp, or a duration relative to the event start.
+/// Durations prefixed with `-` count back from the end of the window instead.
+pub fn parse_time_arg(arg: &str) -> Result<TimeArg, String> {
+    if let Some(duration) = arg.strip_prefix('-') {
+        return parse_duration(duration).map(TimeArg::FromEnd);
+    }
+    if let Some(duration) = arg.strip_prefix('+') {
+        return parse_duration(duration).map(TimeArg::FromStart);
+    }
+    if let Some(timestamp) = parse_timestamp(arg) {
+        return Ok(TimeArg::Absolute(timestamp));
+    }
+    parse_duration(arg).map(TimeArg::FromStart)
+}
+
+/// Parses durations like `90`, `15m` or `1d12h30m`. Bare numbers are seconds.
+pub fn parse_duration(text: &str) -> Result<i64, String> {
+    if text.trim().is_empty() {
+        return Err("empty duration".to_string());
+    }
+    let mut total = 0;
+    let mut number = String::new();
+    for c in text.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'd' => 86400,
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(format!("invalid duration unit '{c}' in '{text}'")),
+        };
+        let value: i64 = number
+            .parse()
+            .map_err(|_| format!("missing number before '{c}' in '{text}'"))?;
+        total += value * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        total += number.parse::<i64>().map_err(|e| e.to_string())?;
+    }
+    Ok(total)
+}
+
+/// Parses `YYYY-MM-DD[ HH:MM[:SS[.fff]]][ UTC|Z|+HH[:MM]]` into a unix timestamp.
+pub fn parse_timestamp(text: &str) -> Option<i64> {
+    let text = text.trim();
+    let (date, time) = match text.find([' ', 'T']) {
+        Some(split) => (&text[..split], text[split + 1..].trim()),
+        None => (text, ""),
+    };
+
+    let mut date_parts = date.split('-');
+    let year: i64 = date_parts.next()?.parse().ok()?;
+    let month: u32 = date_parts.next()?.parse().ok()?;
+    let day: u32 = date_parts.next()?.parse().ok()?;
+    if date_parts.next().is_some() || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let time = time.trim_end_matches("UTC").trim_end_matches('Z').trim_end();
+    let (time, utc_offset) = match time.find(['+', '-']) {
+        Some(split) => (&time[..split], parse_utc_offset(&time[split..])?),
+        None => (time, 0),
+    };
+    let time = time.split('.').next().unwrap();
+
+    let mut seconds = 0;
+    if !time.is_empty() {
+        let mut time_parts = time.split(':');
+        let hours: i64 = time_parts.next()?.parse().ok()?;
+        let minutes: i64 = time_parts.next().unwrap_or("0").parse().ok()?;
+        let secs: i64 = time_parts.next().unwrap_or("0").parse().ok()?;
+        seconds = hours * 3600 + minutes * 60 + secs;
+    }
+
+    Some(days_from_civil(year, month, day) * 86400 + seconds - utc_offset)
+}
+
+fn parse_utc_offset(text: &str) -> Option<i64> {
+    let sign = if text.starts_with('-') { -1 } else { 1 };
+    let digits: String = text[1..].chars().filter(|c| c.is_ascii_digit()).collect();
+    let hours: i64 = digits.get(..2)?.parse().ok()?;
+    let minutes: i64 = digits.get(2..4).unwrap_or("0").parse().ok()?;
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Formats a unix timestamp the way SQLite stores `created_at`.
+pub fn format_timestamp(timestamp: i64) -> String {
+    let (year, month, day) = civil_from_days(timestamp.div_euclid(86400));
+    let seconds = timestamp.rem_euclid(86400);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+// Conversions between unix days and the proleptic gregorian calendar,
+// from http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * shifted_month + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = (if days >= 0 { days } else { days - 146096 }) / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = (if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 }) as u32;
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
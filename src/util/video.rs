@@ -0,0 +1,197 @@
+use std::mem;
+
+use image::RgbaImage;
+
+use crate::config::Event;
+use crate::source::{Placement, PlacementSource};
+use crate::util::camera::Camera;
+use crate::util::encoder::{EncoderThread, Encoding};
+use crate::util::expansion::Expansion;
+use crate::util::hud::Hud;
+use crate::util::leaderboard::Leaderboard;
+use crate::util::render::{FramePacer, FrameSize, Pacing};
+use crate::util::replay::{CanvasReplay, PixelState, ReplayView};
+
+/// Everything between the image a video renderer draws and the encoder: the camera, then the HUD
+/// and the leaderboard on top.
+pub struct Video {
+    encoder: EncoderThread,
+    canvas: FrameSize,
+    frame: FrameSize,
+    camera: Option<Camera>,
+    /// The copy of the picture drawn on before the camera takes its view, kept between frames.
+    copy: RgbaImage,
+    hud: Option<Hud>,
+    leaderboard: Option<Leaderboard>,
+}
+
+impl Video {
+    pub fn start(
+        event: &Event,
+        encoding: &Encoding,
+        fps: u8,
+        name: &str,
+        leaderboard: Option<Leaderboard>,
+    ) -> Self {
+        Video {
+            encoder: EncoderThread::start(encoding, fps, &event.output_path(name), &event.name),
+            canvas: encoding.canvas,
+            frame: encoding.frame,
+            camera: encoding.camera.clone(),
+            copy: RgbaImage::new(0, 0),
+            hud: Hud::new(event, encoding),
+            leaderboard,
+        }
+    }
+
+    pub fn placed(&mut self, replay: &CanvasReplay, placement: &Placement, previous: &PixelState) {
+        if let Some(camera) = &mut self.camera {
+            camera.placed(placement);
+        }
+        if let Some(hud) = &mut self.hud {
+            hud.placed(placement);
+        }
+        if let Some(leaderboard) = &mut self.leaderboard {
+            leaderboard.placed(replay, placement, previous);
+        }
+    }
+
+    /// The frame for `image`, which is laid out on `encoding.canvas`, showing `expansion` as it
+    /// was at `time`.
+    pub fn draw(&mut self, image: &RgbaImage, time: i64, expansion: &Expansion) -> RgbaImage {
+        let mut frame = self.encoder.buffer(self.frame.width, self.frame.height);
+        match &mut self.camera {
+            Some(camera) => camera.view(image, &self.canvas, &mut frame, &self.frame, expansion, time),
+            None => frame.copy_from_slice(image),
+        }
+        self.draw_over(&mut frame, time, expansion);
+        frame
+    }
+
+    /// A copy of `image` to draw on, which `draw_copy` turns into the frame. Without a camera it
+    /// already is the frame.
+    pub fn copy(&mut self, image: &RgbaImage) -> RgbaImage {
+        let mut copy = match self.camera {
+            Some(_) => mem::take(&mut self.copy),
+            None => self.encoder.buffer(self.frame.width, self.frame.height),
+        };
+        if copy.dimensions() != image.dimensions() {
+            copy = RgbaImage::new(image.width(), image.height());
+        }
+        copy.copy_from_slice(image);
+        copy
+    }
+
+    pub fn draw_copy(&mut self, copy: RgbaImage, time: i64, expansion: &Expansion) -> RgbaImage {
+        if self.camera.is_some() {
+            let frame = self.draw(&copy, time, expansion);
+            self.copy = copy;
+            return frame;
+        }
+        let mut frame = copy;
+        self.draw_over(&mut frame, time, expansion);
+        frame
+    }
+
+    fn draw_over(&mut self, frame: &mut RgbaImage, time: i64, expansion: &Expansion) {
+        if let Some(hud) = &mut self.hud {
+            hud.draw(frame, time, expansion);
+        }
+        if let Some(leaderboard) = &mut self.leaderboard {
+            leaderboard.draw(frame, &self.frame);
+        }
+    }
+
+    pub fn write_frame(&self, frame: RgbaImage) {
+        self.encoder.write_frame(frame);
+    }
+
+    pub fn finish(self) {
+        self.encoder.finish();
+    }
+}
+
+/// A picture of the canvas kept up to date by the replay, which the videos are made of.
+pub trait Panel {
+    fn expanded(&mut self, _replay: &CanvasReplay, _previous: &Expansion) {}
+
+    fn placed(&mut self, replay: &CanvasReplay, placement: &Placement, previous: &PixelState);
+
+    /// The picture for the frame at `time`, laid out on `encoding.canvas`.
+    fn frame(&mut self, time: i64, expansion: &Expansion) -> &RgbaImage;
+
+    /// Whether `decorate` has anything to draw on this frame.
+    fn decorated(&self) -> bool {
+        false
+    }
+
+    /// Draws what comes and goes on a copy of the picture, before the camera takes its view.
+    fn decorate(&mut self, _image: &mut RgbaImage, _expansion: &Expansion) {}
+
+    /// Draws on the finished `frame`, after the camera moved and like the HUD, so it stays put.
+    fn overlay(&self, _image: &mut RgbaImage, _frame: &FrameSize) {}
+}
+
+struct PanelView<P> {
+    panel: P,
+    video: Video,
+    pacer: FramePacer,
+}
+
+impl<P: Panel> PanelView<P> {
+    fn write_frame(&mut self, time: i64, expansion: &Expansion) {
+        let mut frame = if self.panel.decorated() {
+            let mut copy = self.video.copy(self.panel.frame(time, expansion));
+            self.panel.decorate(&mut copy, expansion);
+            self.video.draw_copy(copy, time, expansion)
+        } else {
+            self.video.draw(self.panel.frame(time, expansion), time, expansion)
+        };
+        self.panel.overlay(&mut frame, &self.video.frame);
+        self.video.write_frame(frame);
+    }
+}
+
+impl<P: Panel> ReplayView for PanelView<P> {
+    fn before(&mut self, replay: &CanvasReplay, placement: &Placement) {
+        for time in self.pacer.frames_due(placement.timestamp) {
+            self.write_frame(time, replay.expansion());
+        }
+    }
+
+    fn expanded(&mut self, replay: &CanvasReplay, previous: &Expansion) {
+        self.panel.expanded(replay, previous);
+    }
+
+    fn placed(&mut self, replay: &CanvasReplay, placement: &Placement, previous: &PixelState) {
+        self.video.placed(replay, placement, previous);
+        self.panel.placed(replay, placement, previous);
+    }
+}
+
+/// Replays the time window into a video of `panel`, named `name` in the output directory.
+pub async fn render(
+    source: &impl PlacementSource,
+    event: &Event,
+    pacing: &Pacing,
+    encoding: &Encoding,
+    name: &str,
+    panel: impl Panel,
+    leaderboard: Option<Leaderboard>,
+) {
+    let mut view = PanelView {
+        panel,
+        video: Video::start(event, encoding, pacing.frames_per_second, name, leaderboard),
+        pacer: FramePacer::new(pacing, event.window.since),
+    };
+    let mut replay = CanvasReplay::new(&event.schedule, event.mod_actions);
+    replay.run(source.placements(&event.window), &mut view).await;
+
+    if let Some(time) = view.pacer.pending() {
+        view.write_frame(time, replay.expansion());
+    }
+
+    view.video.finish();
+
+    eprintln!("Done!");
+}